@@ -0,0 +1,144 @@
+//! Throughput benchmarks for the batching strategies of `Chunks`.
+//!
+//! Run with `cargo bench`. Every configuration drives an in-memory stream on
+//! a current-thread runtime and reports items/sec, batches/sec and the number
+//! of allocations per batch.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use futures::{stream, Async, Future, Stream};
+use tokio::runtime::current_thread;
+use tokio::timer::Delay;
+use tokio_batch::Chunks;
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const ROUNDS: usize = 5;
+
+struct Config {
+    name: &'static str,
+    items: usize,
+    capacity: usize,
+    duration: Duration,
+    /// Number of items after which the source pauses for a millisecond, which
+    /// lets the timer flush a partial batch.
+    burst: Option<usize>,
+}
+
+/// A source of `items` integers which optionally pauses after every burst.
+fn source(items: usize, burst: Option<usize>) -> impl Stream<Item = usize, Error = io::Error> {
+    let mut next = 0;
+    let mut pause: Option<Delay> = None;
+    stream::poll_fn(move || {
+        if let Some(ref mut delay) = pause {
+            match delay.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(())) => {}
+                Err(e) => return Err(io::Error::other(e)),
+            }
+        }
+        pause = None;
+
+        if next == items {
+            return Ok(Async::Ready(None));
+        }
+        next += 1;
+        if let Some(burst) = burst {
+            if next % burst == 0 {
+                pause = Some(Delay::new(Instant::now() + Duration::from_millis(1)));
+            }
+        }
+        Ok(Async::Ready(Some(next)))
+    })
+}
+
+fn run(config: &Config) {
+    let mut best = Duration::from_secs(u64::MAX);
+    let mut batches = 0;
+    let mut allocations = 0;
+
+    for _ in 0..ROUNDS {
+        let chunks = Chunks::new(
+            source(config.items, config.burst),
+            config.capacity,
+            config.duration,
+        );
+
+        let allocations_before = ALLOCATIONS.load(Ordering::Relaxed);
+        let now = Instant::now();
+        let mut count = 0;
+        let mut items = 0;
+        current_thread::block_on_all(chunks.for_each(|batch| {
+            count += 1;
+            items += batch.len();
+            Ok(())
+        }))
+        .unwrap();
+        let elapsed = now.elapsed();
+        assert_eq!(items, config.items);
+
+        if elapsed < best {
+            best = elapsed;
+            batches = count;
+            allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations_before;
+        }
+    }
+
+    let secs = best.as_secs_f64();
+    println!(
+        "{:<16} {:>14.0} items/s {:>12.0} batches/s {:>8.2} allocs/batch",
+        config.name,
+        config.items as f64 / secs,
+        batches as f64 / secs,
+        allocations as f64 / batches as f64,
+    );
+}
+
+fn main() {
+    let configs = [
+        Config {
+            name: "capacity-only",
+            items: 200_000,
+            capacity: 100,
+            duration: Duration::from_secs(60),
+            burst: None,
+        },
+        Config {
+            name: "timeout-heavy",
+            items: 2_000,
+            capacity: 100,
+            duration: Duration::from_millis(0),
+            burst: Some(10),
+        },
+        Config {
+            name: "small-batches",
+            items: 200_000,
+            capacity: 2,
+            duration: Duration::from_secs(60),
+            burst: None,
+        },
+    ];
+
+    for config in &configs {
+        run(config);
+    }
+}
@@ -1,5 +1,3 @@
-use skeptic;
-
 fn main() {
     skeptic::generate_doc_tests(&["README.md"]);
 }
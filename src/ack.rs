@@ -0,0 +1,245 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use futures::stream::Stream;
+use futures::task::AtomicTask;
+use futures::{Async, Poll};
+
+use crate::{Chunks, Error};
+
+/// An adaptor for `Chunks` with at-least-once delivery of batches.
+///
+/// Every batch is emitted together with an `AckToken`. The batch is retained
+/// until the token is acked, and emitted again if the token is nacked or
+/// dropped without being acked. At most `max_unacked` batches are retained;
+/// once that many are pending, no more items are pulled until some of them
+/// are acked.
+///
+/// The stream only ends after all batches have been acked.
+#[must_use = "streams do nothing unless polled"]
+pub struct AckChunks<S>
+where
+    S: Stream,
+{
+    chunks: Chunks<S>,
+    max_unacked: usize,
+    next_id: u64,
+    unacked: BTreeMap<u64, Vec<S::Item>>,
+    redeliver: VecDeque<u64>,
+    shared: Arc<Shared>,
+}
+
+/// A token to acknowledge a batch emitted by `AckChunks`.
+///
+/// Dropping the token without calling `ack` counts as a `nack`.
+#[derive(Debug)]
+pub struct AckToken {
+    id: u64,
+    shared: Option<Arc<Shared>>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    events: Mutex<Events>,
+    task: AtomicTask,
+}
+
+#[derive(Debug, Default)]
+struct Events {
+    acked: Vec<u64>,
+    nacked: Vec<u64>,
+}
+
+impl AckToken {
+    /// Marks the batch as delivered, so it is not retained any longer.
+    pub fn ack(mut self) {
+        self.settle(true);
+    }
+
+    /// Marks the batch as failed, so it is emitted again.
+    pub fn nack(mut self) {
+        self.settle(false);
+    }
+
+    fn settle(&mut self, acked: bool) {
+        if let Some(shared) = self.shared.take() {
+            {
+                let mut events = shared.events.lock().unwrap();
+                if acked {
+                    events.acked.push(self.id);
+                } else {
+                    events.nacked.push(self.id);
+                }
+            }
+            shared.task.notify();
+        }
+    }
+}
+
+impl Drop for AckToken {
+    fn drop(&mut self) {
+        self.settle(false);
+    }
+}
+
+impl<S> AckChunks<S>
+where
+    S: Stream,
+    S::Item: Clone,
+{
+    pub fn new(chunks: Chunks<S>, max_unacked: usize) -> AckChunks<S> {
+        assert!(max_unacked > 0);
+
+        AckChunks {
+            chunks,
+            max_unacked,
+            next_id: 0,
+            unacked: BTreeMap::new(),
+            redeliver: VecDeque::new(),
+            shared: Arc::new(Shared::default()),
+        }
+    }
+
+    /// Acquires a reference to the underlying `Chunks`.
+    pub fn get_ref(&self) -> &Chunks<S> {
+        &self.chunks
+    }
+
+    /// Acquires a mutable reference to the underlying `Chunks`.
+    pub fn get_mut(&mut self) -> &mut Chunks<S> {
+        &mut self.chunks
+    }
+
+    /// Consumes this combinator, returning the underlying `Chunks`.
+    ///
+    /// Note that this discards all batches that were not acked yet.
+    pub fn into_inner(self) -> Chunks<S> {
+        self.chunks
+    }
+
+    /// Returns the number of emitted batches that were not acked yet.
+    pub fn unacked(&self) -> usize {
+        self.unacked.len()
+    }
+
+    fn settle(&mut self) {
+        let events = mem::take(&mut *self.shared.events.lock().unwrap());
+        for id in events.acked {
+            self.unacked.remove(&id);
+        }
+        for id in events.nacked {
+            if self.unacked.contains_key(&id) {
+                self.redeliver.push_back(id);
+            }
+        }
+    }
+
+    fn token(&self, id: u64) -> AckToken {
+        AckToken {
+            id,
+            shared: Some(self.shared.clone()),
+        }
+    }
+}
+
+impl<S> Stream for AckChunks<S>
+where
+    S: Stream,
+    S::Item: Clone,
+{
+    type Item = (Vec<S::Item>, AckToken);
+    type Error = Error<S::Error>;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.shared.task.register();
+        self.settle();
+
+        // Failed batches are delivered again before any new ones.
+        if let Some(id) = self.redeliver.pop_front() {
+            let batch = self.unacked[&id].clone();
+            return Ok(Async::Ready(Some((batch, self.token(id)))));
+        }
+
+        if self.unacked.len() >= self.max_unacked {
+            return Ok(Async::NotReady);
+        }
+
+        match self.chunks.poll()? {
+            Async::NotReady => Ok(Async::NotReady),
+            Async::Ready(Some(batch)) => {
+                let id = self.next_id;
+                self.next_id += 1;
+                self.unacked.insert(id, batch.clone());
+                Ok(Async::Ready(Some((batch, self.token(id)))))
+            }
+            // Wait for the pending batches, which may still be nacked.
+            Async::Ready(None) if !self.unacked.is_empty() => Ok(Async::NotReady),
+            Async::Ready(None) => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "timer"))]
+mod tests {
+    use super::*;
+    use futures::{future, stream};
+    use std::io;
+    use std::time::Duration;
+
+    fn next<S>(ack_stream: &mut AckChunks<S>) -> (Vec<S::Item>, AckToken)
+    where
+        S: Stream,
+        S::Item: Clone,
+        S::Error: std::fmt::Debug,
+    {
+        match ack_stream.poll() {
+            Ok(Async::Ready(Some(v))) => v,
+            Ok(Async::Ready(None)) => panic!("stream ended"),
+            Ok(Async::NotReady) => panic!("stream not ready"),
+            Err(e) => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn ack_and_nack() {
+        let stream = stream::iter_ok::<_, io::Error>(vec![1, 2, 3, 4, 5]);
+        let chunks = Chunks::new(stream, 2, Duration::new(10, 0));
+        let mut ack_stream = AckChunks::new(chunks, 2);
+
+        tokio::runtime::current_thread::block_on_all(future::lazy(move || {
+            let (first, first_token) = next(&mut ack_stream);
+            assert_eq!(first, vec![1, 2]);
+
+            let (second, second_token) = next(&mut ack_stream);
+            assert_eq!(second, vec![3, 4]);
+
+            // Two batches are pending, so nothing more is pulled.
+            assert!(ack_stream.poll().unwrap().is_not_ready());
+            assert_eq!(ack_stream.unacked(), 2);
+
+            first_token.ack();
+            second_token.nack();
+
+            let (again, again_token) = next(&mut ack_stream);
+            assert_eq!(again, vec![3, 4]);
+            again_token.ack();
+
+            let (last, last_token) = next(&mut ack_stream);
+            assert_eq!(last, vec![5]);
+
+            // The stream does not end while a batch may still be nacked.
+            assert!(ack_stream.poll().unwrap().is_not_ready());
+            drop(last_token);
+
+            let (redelivered, last_token) = next(&mut ack_stream);
+            assert_eq!(redelivered, vec![5]);
+            last_token.ack();
+
+            assert!(ack_stream.poll().unwrap().is_ready());
+            assert_eq!(ack_stream.unacked(), 0);
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+    }
+}
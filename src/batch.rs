@@ -0,0 +1,117 @@
+use std::collections::VecDeque;
+use std::mem;
+
+/// A buffer that `Chunks` collects the items of a batch into.
+///
+/// This is implemented for `Vec` and `VecDeque` and lets other containers,
+/// such as small or fixed size vectors, back a `Chunks` without changing how
+/// batches are formed. Only `with_capacity`, `push`, `len` and `append` are
+/// required; the remaining methods fall back to defaults for containers that
+/// do not manage their allocation. `append` has no default as the trait
+/// cannot iterate over the items of a batch.
+pub trait Batch<T>: Sized {
+    /// Creates an empty batch with room for at least `capacity` items.
+    fn with_capacity(capacity: usize) -> Self;
+
+    /// Appends an item to the batch.
+    fn push(&mut self, item: T);
+
+    /// Returns the number of items in the batch.
+    fn len(&self) -> usize;
+
+    /// Returns whether the batch holds no items.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Takes the items out of the batch, leaving an empty one behind.
+    fn take(&mut self) -> Self {
+        mem::replace(self, Self::with_capacity(0))
+    }
+
+    /// Appends all items of `other` to the batch.
+    fn append(&mut self, other: Self);
+
+    /// Returns the number of items the batch can hold without reallocating.
+    fn capacity(&self) -> usize {
+        usize::MAX
+    }
+
+    /// Reserves room for at least `additional` more items.
+    fn reserve(&mut self, additional: usize) {
+        let _ = additional;
+    }
+
+    /// Reserves room for exactly `additional` more items.
+    fn reserve_exact(&mut self, additional: usize) {
+        self.reserve(additional);
+    }
+}
+
+impl<T> Batch<T> for Vec<T> {
+    fn with_capacity(capacity: usize) -> Self {
+        Vec::with_capacity(capacity)
+    }
+
+    fn push(&mut self, item: T) {
+        Vec::push(self, item);
+    }
+
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn take(&mut self) -> Self {
+        mem::take(self)
+    }
+
+    fn append(&mut self, mut other: Self) {
+        Vec::append(self, &mut other);
+    }
+
+    fn capacity(&self) -> usize {
+        Vec::capacity(self)
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        Vec::reserve(self, additional);
+    }
+
+    fn reserve_exact(&mut self, additional: usize) {
+        Vec::reserve_exact(self, additional);
+    }
+}
+
+impl<T> Batch<T> for VecDeque<T> {
+    fn with_capacity(capacity: usize) -> Self {
+        VecDeque::with_capacity(capacity)
+    }
+
+    fn push(&mut self, item: T) {
+        self.push_back(item);
+    }
+
+    fn len(&self) -> usize {
+        VecDeque::len(self)
+    }
+
+    fn take(&mut self) -> Self {
+        mem::take(self)
+    }
+
+    fn append(&mut self, mut other: Self) {
+        VecDeque::append(self, &mut other);
+    }
+
+    fn capacity(&self) -> usize {
+        VecDeque::capacity(self)
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        VecDeque::reserve(self, additional);
+    }
+
+    fn reserve_exact(&mut self, additional: usize) {
+        VecDeque::reserve_exact(self, additional);
+    }
+}
@@ -0,0 +1,117 @@
+use std::convert::TryFrom;
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+use futures::stream::Stream;
+
+use crate::{check_bounds, BuildError, Chunks};
+
+/// The parameters of a `Chunks`, for example loaded from a configuration
+/// file.
+///
+/// With the `serde` feature this can be deserialized, where only `capacity`
+/// and `timeout_ms` are required. A `Chunks` is built from it with
+/// `Chunks::from_config`, which validates the parameters first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct ChunksConfig {
+    /// The maximum number of items in a batch.
+    pub capacity: usize,
+
+    /// The timeout of a batch in milliseconds.
+    pub timeout_ms: u64,
+
+    /// Flushes a batch with at least this many items once the inner stream
+    /// went idle, see `Chunks::soft_flush_at`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub min_size: Option<usize>,
+
+    /// Merges a final batch with fewer items into the one before, see
+    /// `Chunks::absorb_small_tail`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub min_tail: Option<usize>,
+
+    /// The absolute ceiling on the size of a batch, see `Chunks::hard_cap`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub hard_cap: Option<usize>,
+}
+
+impl ChunksConfig {
+    /// Creates a config with the given capacity and timeout and no optional
+    /// parameters.
+    pub fn new(capacity: usize, timeout: Duration) -> ChunksConfig {
+        ChunksConfig {
+            capacity,
+            timeout_ms: timeout.as_millis() as u64,
+            min_size: None,
+            min_tail: None,
+            hard_cap: None,
+        }
+    }
+
+    /// Checks that a `Chunks` can be built from the parameters.
+    pub fn validate(&self) -> Result<(), BuildError> {
+        if self.capacity == 0 {
+            return Err(BuildError::InvalidCapacity(self.capacity));
+        }
+        check_bounds(
+            self.capacity,
+            &[("min_size", self.min_size), ("min_tail", self.min_tail)],
+            ("hard_cap", self.hard_cap),
+        )
+    }
+}
+
+impl<S> TryFrom<(S, ChunksConfig)> for Chunks<S>
+where
+    S: Stream,
+{
+    type Error = BuildError;
+
+    fn try_from((s, config): (S, ChunksConfig)) -> Result<Chunks<S>, BuildError> {
+        Chunks::from_config(s, &config)
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    use futures::stream;
+    use futures::Future;
+    use std::io;
+
+    #[test]
+    fn build_from_deserialized_config() {
+        // The config is parsed from JSON rather than TOML, as the toml crate
+        // is not available to the build. Both go through the same
+        // `Deserialize` impl.
+        let config: ChunksConfig =
+            serde_json::from_str(r#"{ "capacity": 3, "timeout_ms": 500, "min_tail": 2 }"#).unwrap();
+        assert_eq!(config.timeout_ms, 500);
+        assert_eq!(config.min_size, None);
+
+        let stream = stream::iter_ok::<_, io::Error>(0..7);
+        let chunk_stream = Chunks::try_from((stream, config)).unwrap();
+        let batches = chunk_stream.collect().wait().unwrap();
+        assert_eq!(batches, vec![vec![0, 1, 2], vec![3, 4, 5, 6]]);
+
+        let config: ChunksConfig =
+            serde_json::from_str(r#"{ "capacity": 3, "timeout_ms": 500, "min_size": 4 }"#).unwrap();
+        assert_eq!(
+            config.validate(),
+            Err(BuildError::Inconsistent {
+                field: "min_size",
+                detail: "4 exceeds the capacity 3".to_string()
+            })
+        );
+
+        let config = ChunksConfig::new(0, Duration::from_millis(500));
+        let stream = stream::iter_ok::<_, io::Error>(Vec::<u32>::new());
+        match Chunks::try_from((stream, config)) {
+            Err(e) => assert_eq!(e, BuildError::InvalidCapacity(0)),
+            Ok(_) => panic!("a zero capacity should be rejected"),
+        }
+    }
+}
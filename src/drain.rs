@@ -0,0 +1,114 @@
+use std::vec;
+
+use futures::stream::Stream;
+use futures::{Async, Poll};
+
+use crate::Chunks;
+
+/// A stream yielding the items of a `Chunks` one by one instead of batched.
+///
+/// The items that were buffered come first, followed by an error of the
+/// inner stream that was deferred until they were emitted, and then the
+/// remaining items of the inner stream as they arrive.
+///
+/// This is created by `Chunks::drain_items`.
+#[must_use = "streams do nothing unless polled"]
+pub struct DrainItems<S>
+where
+    S: Stream,
+{
+    buffered: vec::IntoIter<S::Item>,
+    err: Option<S::Error>,
+    stream: Option<S>,
+}
+
+impl<S> DrainItems<S>
+where
+    S: Stream,
+{
+    pub(crate) fn new(mut chunks: Chunks<S>) -> DrainItems<S> {
+        let err = chunks.err.take().and_then(|e| e.into_inner());
+        let done = chunks.done || chunks.stream.is_done();
+        let (stream, buffered) = chunks.into_remaining();
+        DrainItems {
+            buffered: buffered.into_iter(),
+            err,
+            stream: if done { None } else { Some(stream) },
+        }
+    }
+}
+
+impl<S> Stream for DrainItems<S>
+where
+    S: Stream,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, S::Error> {
+        if let Some(item) = self.buffered.next() {
+            return Ok(Async::Ready(Some(item)));
+        }
+        if let Some(e) = self.err.take() {
+            return Err(e);
+        }
+        let polled = match self.stream {
+            Some(ref mut stream) => stream.poll()?,
+            None => return Ok(Async::Ready(None)),
+        };
+        if let Async::Ready(None) = polled {
+            self.stream = None;
+        }
+        Ok(polled)
+    }
+}
+
+#[cfg(all(test, feature = "timer"))]
+mod tests {
+    use super::*;
+    use futures::{future, stream, Future};
+    use std::io;
+    use std::time::Duration;
+
+    #[test]
+    fn drain_items() {
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        let mut chunk_stream = Chunks::with_capacity(rx, 5);
+
+        let drained = future::lazy(move || {
+            for i in 0..3 {
+                tx.unbounded_send(i).unwrap();
+            }
+            assert!(chunk_stream.poll().unwrap().is_not_ready());
+            assert_eq!(chunk_stream.snapshot(), vec![0, 1, 2]);
+
+            for i in 3..5 {
+                tx.unbounded_send(i).unwrap();
+            }
+            drop(tx);
+            chunk_stream.drain_items().collect()
+        })
+        .wait()
+        .unwrap();
+        assert_eq!(drained, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn drain_items_after_error() {
+        let stream = stream::iter_result(vec![Ok(1), Ok(2), Err(io::Error::other("broken"))])
+            .chain(stream::iter_ok(vec![3]));
+        let mut chunk_stream = Chunks::new(stream, 3, Duration::new(10, 0));
+
+        let mut items = future::lazy(move || {
+            // The error is deferred behind the partial batch.
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(Some(vec![1, 2])));
+            assert!(chunk_stream.pending_error().is_some());
+            Ok::<_, ()>(chunk_stream.drain_items().wait())
+        })
+        .wait()
+        .unwrap();
+        assert_eq!(items.next().unwrap().unwrap_err().to_string(), "broken");
+        assert_eq!(items.next().unwrap().unwrap(), 3);
+        assert!(items.next().is_none());
+    }
+}
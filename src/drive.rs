@@ -0,0 +1,457 @@
+use futures::stream::Stream;
+use futures::{try_ready, Async, AsyncSink, Future, IntoFuture, Poll, Sink};
+
+use crate::{Chunks, Error, FlushHandle};
+
+/// Error returned by the futures that drive a `Chunks` into a consumer.
+#[derive(Debug)]
+pub enum DriveError<E, D> {
+    /// The `Chunks` returned an error.
+    Stream(Error<E>),
+
+    /// The consumer of the batches returned an error.
+    Downstream(D),
+}
+
+/// Future that forwards every batch of a `Chunks` into a `Sink`.
+///
+/// No new batch is pulled while the sink does not accept the previous one,
+/// so a slow sink pushes back on the inner stream. The future completes with
+/// the exhausted `Chunks` and the sink once all batches were sent and the sink
+/// was closed.
+///
+/// This is created by `Chunks::forward_batches`.
+#[must_use = "futures do nothing unless polled"]
+pub struct Forward<S, K>
+where
+    S: Stream,
+{
+    chunks: Option<Chunks<S>>,
+    sink: Option<K>,
+    buffered: Option<Vec<S::Item>>,
+}
+
+impl<S, K> Forward<S, K>
+where
+    S: Stream,
+    K: Sink<SinkItem = Vec<S::Item>>,
+{
+    pub(crate) fn new(chunks: Chunks<S>, sink: K) -> Forward<S, K> {
+        Forward {
+            chunks: Some(chunks),
+            sink: Some(sink),
+            buffered: None,
+        }
+    }
+
+    fn sink_mut(&mut self) -> &mut K {
+        self.sink.as_mut().expect("polled Forward after completion")
+    }
+
+    fn chunks_mut(&mut self) -> &mut Chunks<S> {
+        self.chunks
+            .as_mut()
+            .expect("polled Forward after completion")
+    }
+}
+
+impl<S, K> Future for Forward<S, K>
+where
+    S: Stream,
+    K: Sink<SinkItem = Vec<S::Item>>,
+{
+    type Item = (Chunks<S>, K);
+    type Error = DriveError<S::Error, K::SinkError>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut completed = false;
+        loop {
+            // Hand over the pending batch before pulling the next one. If the
+            // sink is full, give it a chance to make room once per batch.
+            if let Some(batch) = self.buffered.take() {
+                if let AsyncSink::NotReady(batch) = self
+                    .sink_mut()
+                    .start_send(batch)
+                    .map_err(DriveError::Downstream)?
+                {
+                    self.buffered = Some(batch);
+                    if completed {
+                        return Ok(Async::NotReady);
+                    }
+                    try_ready!(self
+                        .sink_mut()
+                        .poll_complete()
+                        .map_err(DriveError::Downstream));
+                    completed = true;
+                    continue;
+                }
+                completed = false;
+            }
+
+            match self.chunks_mut().poll().map_err(DriveError::Stream)? {
+                Async::Ready(Some(batch)) => self.buffered = Some(batch),
+                Async::Ready(None) => {
+                    try_ready!(self.sink_mut().close().map_err(DriveError::Downstream));
+                    let chunks = self.chunks.take().unwrap();
+                    let sink = self.sink.take().unwrap();
+                    return Ok(Async::Ready((chunks, sink)));
+                }
+                Async::NotReady => {
+                    try_ready!(self
+                        .sink_mut()
+                        .poll_complete()
+                        .map_err(DriveError::Downstream));
+                    return Ok(Async::NotReady);
+                }
+            }
+        }
+    }
+}
+
+/// Future that forwards the batches of a `Chunks` into a `Sink`, flushing
+/// the current batch early whenever the sink is ready for more.
+///
+/// The sink counts as ready once `poll_complete` reports that everything sent
+/// so far was flushed. While it is, a partial batch is handed over as soon as
+/// the inner stream goes idle instead of waiting for the capacity or the
+/// timeout, so a fast sink sees every item with little delay. While it is
+/// busy, items keep accumulating into the next batch, and a full batch that
+/// the sink rejects pushes back on the inner stream like in `Forward`.
+///
+/// This is created by `Chunks::forward_on_ready`.
+#[must_use = "futures do nothing unless polled"]
+pub struct ForwardOnReady<S, K>
+where
+    S: Stream,
+{
+    forward: Forward<S, K>,
+    handle: FlushHandle,
+}
+
+impl<S, K> ForwardOnReady<S, K>
+where
+    S: Stream,
+    K: Sink<SinkItem = Vec<S::Item>>,
+{
+    pub(crate) fn new(mut chunks: Chunks<S>, sink: K) -> ForwardOnReady<S, K> {
+        let handle = chunks.flush_handle();
+        ForwardOnReady {
+            forward: Forward::new(chunks, sink),
+            handle,
+        }
+    }
+}
+
+impl<S, K> Future for ForwardOnReady<S, K>
+where
+    S: Stream,
+    K: Sink<SinkItem = Vec<S::Item>>,
+{
+    type Item = (Chunks<S>, K);
+    type Error = DriveError<S::Error, K::SinkError>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut requested = false;
+        loop {
+            if let Async::Ready(done) = self.forward.poll()? {
+                return Ok(Async::Ready(done));
+            }
+            // With no batch held back, the sink is ready if it completed
+            // everything that was sent.
+            if requested
+                || self.forward.buffered.is_some()
+                || self.forward.chunks_mut().items.is_empty()
+            {
+                return Ok(Async::NotReady);
+            }
+            if self
+                .forward
+                .sink_mut()
+                .poll_complete()
+                .map_err(DriveError::Downstream)?
+                .is_not_ready()
+            {
+                return Ok(Async::NotReady);
+            }
+            self.handle.flush();
+            requested = true;
+        }
+    }
+}
+
+/// Future that calls a closure with every batch of a `Chunks`.
+///
+/// The future returned by the closure is driven to completion before the
+/// next batch is pulled, so slow processing pushes back on the inner stream.
+/// The future completes once the inner stream ended and all batches were
+/// processed.
+///
+/// This is created by `Chunks::for_each_batch`.
+#[must_use = "futures do nothing unless polled"]
+pub struct ForEachBatch<S, F, U>
+where
+    S: Stream,
+    U: IntoFuture,
+{
+    chunks: Chunks<S>,
+    f: F,
+    pending: Option<U::Future>,
+}
+
+impl<S, F, U> ForEachBatch<S, F, U>
+where
+    S: Stream,
+    F: FnMut(Vec<S::Item>) -> U,
+    U: IntoFuture<Item = ()>,
+{
+    pub(crate) fn new(chunks: Chunks<S>, f: F) -> ForEachBatch<S, F, U> {
+        ForEachBatch {
+            chunks,
+            f,
+            pending: None,
+        }
+    }
+}
+
+impl<S, F, U> Future for ForEachBatch<S, F, U>
+where
+    S: Stream,
+    F: FnMut(Vec<S::Item>) -> U,
+    U: IntoFuture<Item = ()>,
+{
+    type Item = ();
+    type Error = DriveError<S::Error, U::Error>;
+
+    fn poll(&mut self) -> Poll<(), Self::Error> {
+        loop {
+            if let Some(ref mut pending) = self.pending {
+                try_ready!(pending.poll().map_err(DriveError::Downstream));
+            }
+            self.pending = None;
+
+            match try_ready!(self.chunks.poll().map_err(DriveError::Stream)) {
+                Some(batch) => self.pending = Some((self.f)(batch).into_future()),
+                None => return Ok(Async::Ready(())),
+            }
+        }
+    }
+}
+
+/// Future that folds the batches of a `Chunks` into an accumulator.
+///
+/// The future returned by the closure is driven to completion before the
+/// next batch is pulled, so slow processing pushes back on the inner stream.
+/// The future completes with the final accumulator once the inner stream
+/// ended.
+///
+/// This is created by `Chunks::try_fold_batches`.
+#[must_use = "futures do nothing unless polled"]
+pub struct TryFoldBatches<S, F, U, A>
+where
+    S: Stream,
+    U: IntoFuture,
+{
+    chunks: Chunks<S>,
+    f: F,
+    acc: Option<A>,
+    pending: Option<U::Future>,
+}
+
+impl<S, F, U, A> TryFoldBatches<S, F, U, A>
+where
+    S: Stream,
+    F: FnMut(A, Vec<S::Item>) -> U,
+    U: IntoFuture<Item = A>,
+{
+    pub(crate) fn new(chunks: Chunks<S>, init: A, f: F) -> TryFoldBatches<S, F, U, A> {
+        TryFoldBatches {
+            chunks,
+            f,
+            acc: Some(init),
+            pending: None,
+        }
+    }
+}
+
+impl<S, F, U, A> Future for TryFoldBatches<S, F, U, A>
+where
+    S: Stream,
+    F: FnMut(A, Vec<S::Item>) -> U,
+    U: IntoFuture<Item = A>,
+{
+    type Item = A;
+    type Error = DriveError<S::Error, U::Error>;
+
+    fn poll(&mut self) -> Poll<A, Self::Error> {
+        loop {
+            if let Some(ref mut pending) = self.pending {
+                let acc = try_ready!(pending.poll().map_err(DriveError::Downstream));
+                self.acc = Some(acc);
+            }
+            self.pending = None;
+
+            let batch = try_ready!(self.chunks.poll().map_err(DriveError::Stream));
+            let acc = self
+                .acc
+                .take()
+                .expect("polled TryFoldBatches after completion");
+            match batch {
+                Some(batch) => self.pending = Some((self.f)(acc, batch).into_future()),
+                None => return Ok(Async::Ready(acc)),
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "timer"))]
+mod tests {
+    use super::*;
+    use futures::{stream, StartSend};
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    /// A sink that holds at most `limit` batches until it is flushed.
+    struct LimitedSink {
+        limit: usize,
+        pending: Vec<Vec<u32>>,
+        sent: Vec<Vec<u32>>,
+        rejected: usize,
+    }
+
+    impl Sink for LimitedSink {
+        type SinkItem = Vec<u32>;
+        type SinkError = io::Error;
+
+        fn start_send(&mut self, batch: Vec<u32>) -> StartSend<Vec<u32>, io::Error> {
+            if batch.contains(&13) {
+                return Err(io::Error::other("unlucky batch"));
+            }
+            if self.pending.len() >= self.limit {
+                self.rejected += 1;
+                return Ok(AsyncSink::NotReady(batch));
+            }
+            self.pending.push(batch);
+            Ok(AsyncSink::Ready)
+        }
+
+        fn poll_complete(&mut self) -> Poll<(), io::Error> {
+            self.sent.append(&mut self.pending);
+            Ok(Async::Ready(()))
+        }
+
+        fn close(&mut self) -> Poll<(), io::Error> {
+            self.poll_complete()
+        }
+    }
+
+    fn sink(limit: usize) -> LimitedSink {
+        LimitedSink {
+            limit,
+            pending: Vec::new(),
+            sent: Vec::new(),
+            rejected: 0,
+        }
+    }
+
+    #[test]
+    fn forward_with_backpressure() {
+        let stream = stream::iter_ok::<_, io::Error>(0..7);
+        let chunks = Chunks::new(stream, 2, Duration::new(10, 0));
+
+        let (chunks, sink) =
+            tokio::runtime::current_thread::block_on_all(chunks.forward_batches(sink(1))).unwrap();
+        assert_eq!(sink.sent, vec![vec![0, 1], vec![2, 3], vec![4, 5], vec![6]]);
+        assert!(sink.pending.is_empty());
+        assert_eq!(sink.rejected, 3);
+        assert_eq!(chunks.batches_emitted(), 4);
+    }
+
+    #[test]
+    fn forward_errors() {
+        let stream = stream::iter_ok::<_, io::Error>(10..20);
+        let chunks = Chunks::new(stream, 2, Duration::new(10, 0));
+        match tokio::runtime::current_thread::block_on_all(chunks.forward_batches(sink(4))) {
+            Err(DriveError::Downstream(e)) => assert_eq!(e.to_string(), "unlucky batch"),
+            Err(e) => panic!("{:?}", e),
+            Ok(_) => panic!("forwarding should fail"),
+        }
+
+        let stream = stream::iter_result(vec![Ok(1), Err(io::Error::other("broken source"))]);
+        let chunks = Chunks::new(stream, 2, Duration::new(10, 0));
+        match tokio::runtime::current_thread::block_on_all(chunks.forward_batches(sink(4))) {
+            Err(DriveError::Stream(e)) => assert!(e.is_inner()),
+            Err(e) => panic!("{:?}", e),
+            Ok(_) => panic!("forwarding should fail"),
+        }
+    }
+
+    #[test]
+    fn for_each_batch() {
+        let stream = stream::iter_ok::<_, io::Error>(0..5);
+        let chunks = Chunks::new(stream, 2, Duration::new(10, 0));
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let sink = seen.clone();
+        let processed = chunks.for_each_batch(move |batch| {
+            sink.borrow_mut().push(batch);
+            Ok::<_, io::Error>(())
+        });
+        tokio::runtime::current_thread::block_on_all(processed).unwrap();
+        assert_eq!(*seen.borrow(), vec![vec![0, 1], vec![2, 3], vec![4]]);
+
+        let stream = stream::iter_ok::<_, io::Error>(0..5);
+        let chunks = Chunks::new(stream, 2, Duration::new(10, 0));
+        let processed = chunks.for_each_batch(|batch| {
+            if batch.contains(&3) {
+                Err("cannot process 3")
+            } else {
+                Ok(())
+            }
+        });
+        match tokio::runtime::current_thread::block_on_all(processed) {
+            Err(DriveError::Downstream(e)) => assert_eq!(e, "cannot process 3"),
+            Err(e) => panic!("{:?}", e),
+            Ok(()) => panic!("processing should fail"),
+        }
+    }
+
+    #[test]
+    fn try_fold_batches() {
+        let stream = stream::iter_ok::<_, io::Error>(1..=10);
+        let chunks = Chunks::new(stream, 3, Duration::new(10, 0));
+        let total = chunks.try_fold_batches(0, |total, batch| {
+            Ok::<_, io::Error>(total + batch.iter().sum::<u32>())
+        });
+        assert_eq!(
+            tokio::runtime::current_thread::block_on_all(total).unwrap(),
+            55
+        );
+
+        let stream =
+            stream::iter_result(vec![Ok(1), Ok(2), Err(io::Error::other("broken source"))]);
+        let chunks = Chunks::new(stream, 3, Duration::new(10, 0));
+        let total = chunks.try_fold_batches(0, |total, batch| {
+            if batch.contains(&1) {
+                Err("cannot fold 1")
+            } else {
+                Ok(total + batch.len())
+            }
+        });
+        match tokio::runtime::current_thread::block_on_all(total) {
+            Err(DriveError::Downstream(e)) => assert_eq!(e, "cannot fold 1"),
+            Err(e) => panic!("{:?}", e),
+            Ok(total) => panic!("unexpected total {}", total),
+        }
+
+        let stream = stream::iter_result(vec![Ok(1), Err(io::Error::other("broken source"))]);
+        let chunks = Chunks::new(stream, 3, Duration::new(10, 0));
+        let total = chunks.try_fold_batches(0, |total, batch| Ok::<_, ()>(total + batch.len()));
+        match tokio::runtime::current_thread::block_on_all(total) {
+            Err(DriveError::Stream(e)) => assert!(e.is_inner()),
+            Err(e) => panic!("{:?}", e),
+            Ok(total) => panic!("unexpected total {}", total),
+        }
+    }
+}
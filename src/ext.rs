@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use futures::stream::Stream;
+
+use crate::{Chunks, SplitChunks, WindowChunks};
+
+/// An extension trait adding the adaptors of this crate to every `Stream`.
+///
+/// This is most easily brought into scope with the prelude:
+///
+/// ```
+/// use std::io;
+/// use std::time::Duration;
+///
+/// use futures::{stream, Stream};
+/// use tokio::runtime::current_thread;
+/// use tokio_batch::prelude::*;
+///
+/// let chunk_stream = stream::iter_ok::<_, io::Error>(vec![1, 2, 3])
+///     .chunks_timeout(2, Duration::new(10, 0));
+///
+/// let v = current_thread::block_on_all(chunk_stream.collect()).unwrap();
+/// assert_eq!(v, vec![vec![1, 2], vec![3]]);
+/// ```
+pub trait ChunksExt: Stream + Sized {
+    /// Chunks up the items of this stream, see `Chunks::new`.
+    #[track_caller]
+    fn chunks_timeout(self, capacity: usize, duration: Duration) -> Chunks<Self> {
+        Chunks::new(self, capacity, duration)
+    }
+
+    /// Emits sliding windows over the items of this stream, see
+    /// `WindowChunks::new`.
+    fn window_timeout(self, window: usize, step: usize, duration: Duration) -> WindowChunks<Self>
+    where
+        Self::Item: Clone,
+    {
+        WindowChunks::new(self, window, step, duration)
+    }
+
+    /// Chunks up the successful items and the errors of a stream of `Result`s
+    /// separately, see `SplitChunks::new`.
+    fn split_results<T, E>(
+        self,
+        capacity: usize,
+        error_capacity: usize,
+        duration: Duration,
+    ) -> SplitChunks<Self, T, E>
+    where
+        Self: Stream<Item = Result<T, E>>,
+    {
+        SplitChunks::new(self, capacity, error_capacity, duration)
+    }
+}
+
+impl<S: Stream> ChunksExt for S {}
@@ -0,0 +1,85 @@
+use std::time::{Duration, Instant};
+
+/// The number of most recent gaps the percentiles are computed from.
+const RESERVOIR: usize = 1024;
+
+/// The distribution of the time gaps between consecutive items pulled from
+/// the inner stream, see `Chunks::arrival_gap_stats`.
+///
+/// `min`, `max` and `mean` cover all gaps, while the percentiles are computed
+/// from the most recent 1024 gaps. All fields are zero until two items
+/// arrived.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GapStats {
+    /// The number of gaps recorded.
+    pub count: u64,
+
+    /// The shortest gap.
+    pub min: Duration,
+
+    /// The longest gap.
+    pub max: Duration,
+
+    /// The average gap.
+    pub mean: Duration,
+
+    /// The median of the recent gaps.
+    pub p50: Duration,
+
+    /// The 99th percentile of the recent gaps.
+    pub p99: Duration,
+}
+
+/// Records the gaps between arrivals in a running summary and a ring buffer
+/// of the recent ones.
+#[derive(Debug, Default)]
+pub(crate) struct GapRecorder {
+    last: Option<Instant>,
+    count: u64,
+    min: Duration,
+    max: Duration,
+    total: Duration,
+    recent: Vec<Duration>,
+    next: usize,
+}
+
+impl GapRecorder {
+    pub(crate) fn record(&mut self, at: Instant) {
+        let gap = match self.last.replace(at) {
+            Some(last) => at.saturating_duration_since(last),
+            None => return,
+        };
+        self.min = if self.count == 0 {
+            gap
+        } else {
+            self.min.min(gap)
+        };
+        self.max = self.max.max(gap);
+        self.total = self.total.saturating_add(gap);
+        self.count = self.count.saturating_add(1);
+        if self.recent.len() < RESERVOIR {
+            self.recent.push(gap);
+        } else {
+            self.recent[self.next] = gap;
+            self.next = (self.next + 1) % RESERVOIR;
+        }
+    }
+
+    pub(crate) fn stats(&self) -> GapStats {
+        if self.count == 0 {
+            return GapStats::default();
+        }
+        let mut recent = self.recent.clone();
+        recent.sort_unstable();
+        // The nearest rank of the percentile, which is always a recorded gap.
+        let percentile = |p: usize| recent[(recent.len() * p).div_ceil(100).max(1) - 1];
+        GapStats {
+            count: self.count,
+            min: self.min,
+            max: self.max,
+            mean: Duration::from_nanos((self.total.as_nanos() / u128::from(self.count)) as u64),
+            p50: percentile(50),
+            p99: percentile(99),
+        }
+    }
+}
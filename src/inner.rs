@@ -0,0 +1,84 @@
+use futures::stream::{Fuse, Stream};
+use futures::{Async, Poll};
+
+/// The stream a `Chunks` pulls its items from, either fused or polled
+/// directly.
+pub(crate) enum Inner<S> {
+    Fused(Fuse<S>),
+
+    /// Created by `Chunks::new_unchecked`, which trusts the caller to never
+    /// poll after completion. Debug builds still check this.
+    Unfused {
+        stream: S,
+        done: bool,
+    },
+}
+
+impl<S> Inner<S>
+where
+    S: Stream,
+{
+    pub(crate) fn fused(s: S) -> Inner<S> {
+        Inner::Fused(s.fuse())
+    }
+
+    pub(crate) fn unfused(s: S) -> Inner<S> {
+        Inner::Unfused {
+            stream: s,
+            done: false,
+        }
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        match *self {
+            Inner::Fused(ref stream) => stream.is_done(),
+            Inner::Unfused { done, .. } => done,
+        }
+    }
+
+    pub(crate) fn get_ref(&self) -> &S {
+        match *self {
+            Inner::Fused(ref stream) => stream.get_ref(),
+            Inner::Unfused { ref stream, .. } => stream,
+        }
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut S {
+        match *self {
+            Inner::Fused(ref mut stream) => stream.get_mut(),
+            Inner::Unfused { ref mut stream, .. } => stream,
+        }
+    }
+
+    pub(crate) fn into_inner(self) -> S {
+        match self {
+            Inner::Fused(stream) => stream.into_inner(),
+            Inner::Unfused { stream, .. } => stream,
+        }
+    }
+}
+
+impl<S> Stream for Inner<S>
+where
+    S: Stream,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, S::Error> {
+        match *self {
+            Inner::Fused(ref mut stream) => stream.poll(),
+            Inner::Unfused {
+                ref mut stream,
+                ref mut done,
+            } => {
+                debug_assert!(!*done, "inner stream polled after completion");
+                let res = stream.poll();
+                if let Ok(Async::Ready(None)) = res {
+                    *done = true;
+                }
+                res
+            }
+        }
+    }
+}
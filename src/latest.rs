@@ -0,0 +1,152 @@
+use futures::stream::Stream;
+use futures::{Async, Poll};
+
+use crate::{Chunks, Error};
+
+/// The most batches a single poll of `LatestBatch` pulls from its `Chunks`.
+const MAX_DRAINED: usize = 32;
+
+/// An adaptor for `Chunks` that only emits the most recent batch.
+///
+/// Every poll drains the batches the `Chunks` can complete without waiting,
+/// up to 32 of them so that a source which is always ready cannot hold up
+/// the task, and emits only the last of them. The older ones are dropped, so
+/// a slow consumer always sees the freshest batch instead of working through
+/// a backlog. This loses data: every item of a dropped batch is gone, and the
+/// only trace of them is `dropped`. The partial batch that is accumulated
+/// while the consumer is busy is not affected.
+///
+/// This is created by `Chunks::latest_batch`.
+#[must_use = "streams do nothing unless polled"]
+pub struct LatestBatch<S>
+where
+    S: Stream,
+{
+    chunks: Chunks<S>,
+    err: Option<Error<S::Error>>,
+    dropped: u64,
+}
+
+impl<S> LatestBatch<S>
+where
+    S: Stream,
+{
+    pub(crate) fn new(chunks: Chunks<S>) -> LatestBatch<S> {
+        LatestBatch {
+            chunks,
+            err: None,
+            dropped: 0,
+        }
+    }
+
+    /// Acquires a reference to the underlying `Chunks`.
+    pub fn get_ref(&self) -> &Chunks<S> {
+        &self.chunks
+    }
+
+    /// Acquires a mutable reference to the underlying `Chunks`.
+    pub fn get_mut(&mut self) -> &mut Chunks<S> {
+        &mut self.chunks
+    }
+
+    /// Consumes this combinator, returning the underlying `Chunks`.
+    pub fn into_inner(self) -> Chunks<S> {
+        self.chunks
+    }
+
+    /// Returns the number of batches that were dropped in favour of a newer
+    /// one.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+impl<S> Stream for LatestBatch<S>
+where
+    S: Stream,
+{
+    type Item = Vec<S::Item>;
+    type Error = Error<S::Error>;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if let Some(err) = self.err.take() {
+            return Err(err);
+        }
+
+        let mut latest = None;
+        for _ in 0..MAX_DRAINED {
+            match self.chunks.poll() {
+                Ok(Async::Ready(Some(batch))) => {
+                    if latest.replace(batch).is_some() {
+                        self.dropped = self.dropped.saturating_add(1);
+                    }
+                }
+                Ok(Async::Ready(None)) if latest.is_none() => return Ok(Async::Ready(None)),
+                Ok(Async::Ready(None)) | Ok(Async::NotReady) => break,
+                // Emit the batch that completed before the error first.
+                Err(e) => match latest {
+                    Some(_) => {
+                        self.err = Some(e);
+                        break;
+                    }
+                    None => return Err(e),
+                },
+            }
+        }
+        match latest {
+            Some(batch) => Ok(Async::Ready(Some(batch))),
+            None => Ok(Async::NotReady),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "timer"))]
+mod tests {
+    use super::*;
+    use futures::sync::mpsc;
+    use futures::{future, stream};
+    use std::io;
+    use std::time::Duration;
+
+    #[test]
+    fn only_the_latest_batch_is_delivered() {
+        let (tx, rx) = mpsc::unbounded::<u32>();
+        let mut latest = Chunks::new(rx, 2, Duration::new(10, 0)).latest_batch();
+
+        tokio::runtime::current_thread::block_on_all(future::lazy(move || {
+            // Three batches complete before the consumer polls again.
+            for i in 0..7 {
+                tx.unbounded_send(i).unwrap();
+            }
+            assert_eq!(latest.poll().unwrap(), Async::Ready(Some(vec![4, 5])));
+            assert_eq!(latest.dropped(), 2);
+            assert!(latest.poll().unwrap().is_not_ready());
+
+            tx.unbounded_send(7).unwrap();
+            assert_eq!(latest.poll().unwrap(), Async::Ready(Some(vec![6, 7])));
+
+            tx.unbounded_send(8).unwrap();
+            drop(tx);
+            assert_eq!(latest.poll().unwrap(), Async::Ready(Some(vec![8])));
+            assert_eq!(latest.poll().unwrap(), Async::Ready(None));
+            assert_eq!(latest.dropped(), 2);
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn always_ready_source() {
+        let stream = stream::repeat::<_, io::Error>(1);
+        let mut latest = Chunks::new(stream, 2, Duration::new(10, 0)).latest_batch();
+
+        tokio::runtime::current_thread::block_on_all(future::lazy(move || {
+            // The drain stops after a bounded number of batches.
+            assert_eq!(latest.poll().unwrap(), Async::Ready(Some(vec![1, 1])));
+            assert_eq!(latest.dropped(), MAX_DRAINED as u64 - 1);
+            assert_eq!(latest.poll().unwrap(), Async::Ready(Some(vec![1, 1])));
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+    }
+}
@@ -1,11 +1,94 @@
+#[cfg(feature = "timer")]
+use std::collections::HashSet;
+#[cfg(feature = "timer")]
+use std::convert::Infallible;
+use std::error;
+use std::fmt;
+#[cfg(feature = "timer")]
+use std::hash::Hash;
 use std::mem;
 use std::prelude::v1::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use futures::stream::{Fuse, Stream};
-use futures::{Async, Future, Poll};
-use tokio::timer;
-use tokio::timer::Delay;
+#[cfg(feature = "metrics")]
+use crate::gaps::GapRecorder;
+use crate::inner::Inner;
+use crate::rate_limit::RateLimit;
+use crate::state::State;
+use crate::time::{clock, timer, Delay};
+use crate::trigger::Trigger;
+#[cfg(feature = "timer")]
+use futures::stream::{self, PollFn};
+use futures::stream::{Map, MapErr, Stream};
+#[cfg(feature = "timer")]
+use futures::sync::mpsc;
+use futures::sync::oneshot;
+use futures::task;
+use futures::{Async, Future, IntoFuture, Poll, Sink};
+
+mod ack;
+mod batch;
+#[cfg(feature = "timer")]
+mod config;
+mod drain;
+mod drive;
+#[cfg(feature = "timer")]
+mod ext;
+#[cfg(feature = "metrics")]
+mod gaps;
+mod inner;
+mod latest;
+mod map;
+mod offset;
+mod pause;
+mod pool;
+mod rate_limit;
+mod ready;
+mod round_robin;
+mod sink;
+#[cfg(feature = "timer")]
+mod split;
+mod state;
+mod time;
+mod timestamp;
+mod trigger;
+#[cfg(feature = "timer")]
+mod window;
+
+pub use crate::ack::{AckChunks, AckToken};
+pub use crate::batch::Batch;
+#[cfg(feature = "timer")]
+pub use crate::config::ChunksConfig;
+pub use crate::drain::DrainItems;
+pub use crate::drive::{DriveError, ForEachBatch, Forward, ForwardOnReady, TryFoldBatches};
+#[cfg(feature = "timer")]
+pub use crate::ext::ChunksExt;
+#[cfg(feature = "metrics")]
+pub use crate::gaps::GapStats;
+pub use crate::latest::LatestBatch;
+pub use crate::map::MapOkBatch;
+pub use crate::offset::{HasOffset, OffsetChunks};
+pub use crate::pause::PauseHandle;
+pub use crate::pool::{BufferPool, PooledBatch, PooledChunks};
+pub use crate::ready::ReadyBatches;
+pub use crate::round_robin::RoundRobin;
+pub use crate::sink::BatchSink;
+#[cfg(feature = "timer")]
+pub use crate::split::{BatchOutput, SplitChunks};
+pub use crate::timestamp::{BatchInfo, Batched, Timestamped};
+pub use crate::trigger::FlushHandle;
+#[cfg(feature = "timer")]
+pub use crate::window::WindowChunks;
+
+/// A prelude that brings the extension trait and the most common types of
+/// this crate into scope with `use tokio_batch::prelude::*;`.
+pub mod prelude {
+    pub use crate::{Chunks, Error};
+    #[cfg(feature = "timer")]
+    pub use crate::{ChunksExt, WindowChunks};
+}
 
 /// An adaptor that chunks up elements in a vector.
 ///
@@ -13,24 +96,463 @@ use tokio::timer::Delay;
 /// vector used for buffering when a specified capacity has been reached
 /// or a predefined timeout was triggered.
 ///
+/// The items do not need to be `'static`, so a stream of references
+/// borrowing from a local buffer can be batched into `Vec<&T>` without
+/// copying. The combinator then has to be driven on an executor that does not
+/// require `'static` futures, such as `current_thread::block_on_all`. Only
+/// `boxed`, `boxed_local` and `spawn` require a `'static` stream, and only
+/// `distinct` and `flush_on` require `'static` items.
+///
+/// Batches are collected into a `Vec` unless another `Batch` container is
+/// picked with `Chunks::with_batch`.
+///
+/// # Callbacks
+///
+/// The callbacks of options such as `item_timeout`, `max_memory` or
+/// `sort_by_key` are called during `poll` and only ever see an item or the
+/// batch, never the `Chunks` itself. As they must be `'static`, they cannot
+/// borrow local state, such as another `Chunks`:
+///
+/// ```compile_fail,E0597
+/// use std::io;
+/// use std::time::Duration;
+///
+/// use futures::stream;
+/// use tokio_batch::Chunks;
+///
+/// let stream = stream::iter_ok::<_, io::Error>(vec![1, 2, 3]);
+/// let mut chunk_stream = Chunks::new(stream, 2, Duration::new(10, 0));
+/// let chunks = &mut chunk_stream;
+///
+/// let stream = stream::iter_ok::<_, io::Error>(vec![4, 5, 6]);
+/// let other = Chunks::new(stream, 2, Duration::new(10, 0)).item_timeout(move |_| {
+///     if chunks.is_timer_armed() {
+///         Duration::from_millis(5)
+///     } else {
+///         Duration::new(10, 0)
+///     }
+/// });
+/// ```
+///
+/// Handing a combinator to its own callbacks through shared ownership, such
+/// as an `Arc<Mutex<_>>`, compiles but deadlocks once a callback runs.
+///
+/// # Cancellation
+///
+/// Dropping a `Chunks` drops the items of the batch it is accumulating. To
+/// stop batching without losing them, take them out with `into_remaining`
+/// instead, which also hands back the inner stream.
+///
 /// This was taken and adjusted from
 /// https://github.com/alexcrichton/futures-rs/blob/master/src/stream/chunks.rs
 /// and moved into a separate crate for usability.
 #[must_use = "streams do nothing unless polled"]
-pub struct Chunks<S>
+pub struct Chunks<S, B = Vec<<S as Stream>::Item>>
 where
     S: Stream,
 {
     clock: Option<Delay>,
     duration: Duration,
-    items: Vec<S::Item>,
+    capacity: usize,
+    hard_cap: Option<usize>,
+    #[cfg(feature = "metrics")]
+    gaps: GapRecorder,
+    growth_policy: GrowthPolicy,
+    schedule: Option<(ScheduleFn, u64)>,
+    items: B,
     err: Option<Error<S::Error>>,
-    stream: Fuse<S>,
+    stream: Inner<S>,
+    item_timeout: Option<ItemFn<S::Item, Duration>>,
+    jitter: Option<Jitter>,
+    rate_limit: Option<RateLimit>,
+    pause: Option<PauseHandle>,
+    flush_handle: Option<FlushHandle>,
+    pressure: Option<Arc<AtomicBool>>,
+    flush_when: Option<(FlushFutureFn, Option<FlushFuture>)>,
+    last_reason: Option<FlushReason>,
+    memory: Option<MemoryLimit<S::Item>>,
+    weight: Option<Weight<S::Item>>,
+    grace: Option<Grace>,
+    filter: Option<Box<dyn Filter<S::Item>>>,
+    boundary: Option<(ItemFn<S::Item, bool>, Sentinel)>,
+    runs: Option<RunFn<S::Item>>,
+    shutdown: Option<oneshot::Receiver<()>>,
+    ignore_shutdown_cancel: bool,
+    completing: bool,
+    done: bool,
+    absorb_small_tail: Option<usize>,
+    held: Option<(B, Flushed)>,
+    taken: Usage,
+    created_at: Instant,
+    started_at: Option<Instant>,
+    flushed_at: Option<(Instant, Instant)>,
+    retry_inner: Option<usize>,
+    hold_on_error: bool,
+    inner_failures: usize,
+    carried: Option<(S::Item, usize, Option<Sentinel>)>,
+    counters: Counters,
+    sort: Option<SortFn<B>>,
+    priority: Option<(PriorityFn<S::Item>, SortFn<B>)>,
+    priority_timeout: Option<Duration>,
+    inspect: Option<ItemFn<S::Item, ()>>,
+    progress: Option<(usize, ProgressFn)>,
+    validate: Option<(ValidateFn<B>, OnReject)>,
+    validating: Option<(B, Validation, Usage)>,
+    pad: Option<BatchFn<B>>,
+    unpadded_len: usize,
+    yield_after: Option<usize>,
+    ready_streak: usize,
+    soft_flush_at: Option<usize>,
+    timeout_cycle: Option<(Vec<Duration>, usize)>,
+    catchup: Option<(Duration, bool)>,
+    first_deadline: Option<Instant>,
+    min_latency: Option<Duration>,
+    floor: Option<Delay>,
+    mode: FlushMode,
+    tick: Option<Instant>,
+    heartbeat: Option<(Duration, Option<Delay>)>,
+    missed_tick: MissedTickBehavior,
+    timer_policy: TimerPolicy,
+    label: Option<&'static str>,
+}
+
+impl<S, B> fmt::Debug for Chunks<S, B>
+where
+    S: Stream,
+    B: Batch<S::Item>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Chunks")
+            .field("label", &self.label)
+            .field("capacity", &self.capacity)
+            .field("duration", &self.duration)
+            .field("mode", &self.mode)
+            .field("buffered", &self.items.len())
+            .field("counters", &self.counters)
+            .field("done", &self.done)
+            .finish()
+    }
+}
+
+/// The most items the buffer of a batch is allocated for up front.
+pub const MAX_PREALLOCATE: usize = 8192;
+
+/// How the buffer for a batch is allocated and grown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrowthPolicy {
+    /// Allocate room for the whole capacity up front.
+    ///
+    /// At most `MAX_PREALLOCATE` items are allocated, so a huge capacity,
+    /// e.g. `usize::MAX` to only flush on the timeout, does not attempt a
+    /// huge allocation. Beyond that the buffer doubles its size as required.
+    Preallocate,
+
+    /// Start with an empty buffer and let it double its size as required.
+    Doubling,
+
+    /// Start with room for the given number of items and grow the buffer by
+    /// the same amount whenever it is full.
+    FixedStep(usize),
+}
+
+/// Which triggers flush a batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushMode {
+    /// Flush when the capacity is reached or the timeout fires, whichever
+    /// comes first.
+    Hybrid,
+
+    /// Only flush when the capacity is reached.
+    CapacityOnly,
+
+    /// Only flush when the timeout fires, regardless of the capacity.
+    ///
+    /// After the first batch, the timeouts follow a fixed schedule of ticks
+    /// spaced by the timeout, so the flushes do not drift over long runs.
+    Interval,
+}
+
+/// What happens to the ticks of `FlushMode::Interval` that were missed.
+///
+/// A tick is missed if it passed before the batch it belongs to was armed,
+/// e.g. because the task was busy or no items arrived for a while.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissedTickBehavior {
+    /// Flush immediately for every missed tick until the schedule caught up.
+    Burst,
+
+    /// Drop the missed ticks and continue with the next tick of the
+    /// schedule.
+    Skip,
+}
+
+/// What happens to a running timer when a batch is flushed because it
+/// reached its capacity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerPolicy {
+    /// Drop the timer, so the next batch arms a fresh one with its first item.
+    Reset,
+
+    /// Keep the timer, so the next batch is flushed when the timer of the
+    /// batch before would have fired.
+    Keep,
+}
+
+/// What happens to a batch that was rejected by `Chunks::validate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnReject {
+    /// The batch is dropped.
+    Drop,
+
+    /// The items are buffered again and emitted with the next batch.
+    Rebuffer,
+}
+
+/// What happens to the item that marks the end of a batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sentinel {
+    /// The item is included as the last item of the batch.
+    Include,
+
+    /// The item is dropped.
+    Drop,
+}
+
+/// The lane of an item in a batch of `Chunks::prioritize`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// The item is emitted before all low priority items of its batch.
+    High,
+
+    /// The item is emitted after all high priority items of its batch.
+    Low,
+}
+
+/// Activity counters of a `Chunks`.
+///
+/// The counters saturate at `u64::MAX` instead of wrapping around, so a rate
+/// derived from two readings never goes negative.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Counters {
+    /// Number of items pulled from the inner stream.
+    pub items: u64,
+
+    /// Number of batches emitted.
+    pub batches: u64,
+
+    /// Number of batches flushed because the timer fired.
+    pub timeouts: u64,
+
+    /// Number of batches flushed because they reached the capacity.
+    pub capacity_flushes: u64,
+}
+
+/// Why a batch was flushed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushReason {
+    /// The batch reached its capacity.
+    Capacity,
+
+    /// The batch reached the ceiling of `Chunks::hard_cap`.
+    HardCap,
+
+    /// The weights of the items reached the maximum of `Chunks::weighted`, or
+    /// the next item would have exceeded it.
+    Weight,
+
+    /// The buffered items took up the budget of `Chunks::max_memory`.
+    Memory,
+
+    /// The timer fired.
+    Timeout,
+
+    /// A tick of `Chunks::with_capacity_and_interval` fired.
+    Heartbeat,
+
+    /// An item matched the boundary of `Chunks::flush_on`.
+    Boundary,
+
+    /// An item with a different key than the batch arrived, see
+    /// `Chunks::group_runs`.
+    KeyChange,
+
+    /// A flush was requested through a `FlushHandle`.
+    Signal,
+
+    /// The flag of `Chunks::flush_on_pressure` was set.
+    Pressure,
+
+    /// A future of `Chunks::flush_when` resolved.
+    Custom,
+
+    /// The batch was flushed by `Chunks::reconfigure_and_flush`.
+    Reconfigure,
+
+    /// The stream was shut down or completed with `Chunks::complete`.
+    Shutdown,
+
+    /// The inner stream went idle with a batch of at least `soft_flush_at`
+    /// items.
+    Idle,
+
+    /// The inner stream or the timer failed, and the error is returned after
+    /// this batch.
+    Error,
+
+    /// The inner stream failed and `Chunks::retry_inner` swallowed the
+    /// error, so no error follows this batch.
+    Retry,
+
+    /// The inner stream ended.
+    End,
+}
+
+/// A source of random numbers for jittered timeouts.
+///
+/// This is implemented for closures returning a `u64`, so a seeded generator
+/// or a fixed sequence can be plugged in without depending on a particular
+/// RNG crate.
+pub trait NextU64: Send {
+    /// Returns the next random number.
+    fn next_u64(&mut self) -> u64;
+}
+
+impl<F> NextU64 for F
+where
+    F: FnMut() -> u64 + Send,
+{
+    fn next_u64(&mut self) -> u64 {
+        self()
+    }
+}
+
+/// Adds a random offset of up to `max` to the timeout of every batch.
+struct Jitter {
+    max: Duration,
+    rng: Box<dyn NextU64>,
+}
+
+impl Jitter {
+    fn offset(&mut self) -> Duration {
+        let max = self.max.as_nanos().min(u128::from(u64::MAX - 1)) as u64;
+        Duration::from_nanos(self.rng.next_u64() % (max + 1))
+    }
+}
+
+/// Flushes a batch once the weights of its items add up to `target`, and
+/// holds back an item that would take it beyond `max`.
+struct Weight<T> {
+    weigh: ItemFn<T, Result<usize, BoxError>>,
+    target: usize,
+    max: usize,
+    used: usize,
+}
+
+/// Extends the timer of a batch that is almost full.
+struct Grace {
+    threshold: f64,
+    extension: Duration,
+    extended: bool,
+}
+
+/// Bounds the memory taken up by the buffered items.
+struct MemoryLimit<T> {
+    size_of: ItemFn<T, usize>,
+    max: usize,
+    used: usize,
+}
+
+/// The memory and weight a batch took up when it was taken out of the buffer,
+/// so that they can be accounted again if `validate` buffers it again.
+#[derive(Debug, Default, Clone, Copy)]
+struct Usage {
+    memory: usize,
+    weight: usize,
+}
+
+impl Usage {
+    fn add(self, other: Usage) -> Usage {
+        Usage {
+            memory: self.memory.saturating_add(other.memory),
+            weight: self.weight.saturating_add(other.weight),
+        }
+    }
+}
+
+/// What is known about a flushed batch that is held back by
+/// `absorb_small_tail`, so that it is reported once the batch is emitted.
+#[derive(Debug, Clone, Copy)]
+struct Flushed {
+    usage: Usage,
+    reason: Option<FlushReason>,
+    times: Option<(Instant, Instant)>,
+}
+
+/// Decides which items of a batch are buffered.
+trait Filter<T>: Send {
+    fn accept(&mut self, item: &T) -> bool;
+
+    /// Called whenever a new batch is started.
+    fn reset(&mut self);
+}
+
+#[cfg(feature = "timer")]
+struct Distinct<T> {
+    seen: HashSet<T>,
+}
+
+#[cfg(feature = "timer")]
+impl<T> Filter<T> for Distinct<T>
+where
+    T: Hash + Eq + Clone + Send,
+{
+    fn accept(&mut self, item: &T) -> bool {
+        if self.seen.contains(item) {
+            false
+        } else {
+            self.seen.insert(item.clone());
+            true
+        }
+    }
+
+    fn reset(&mut self) {
+        self.seen.clear();
+    }
 }
 
+fn pad_with_default<T: Default>(batch: &mut Vec<T>, len: usize) {
+    batch.resize_with(len, T::default);
+}
+
+type ItemFn<T, R> = Box<dyn Fn(&T) -> R + Send>;
+type BatchFn<B> = fn(&mut B, usize);
+type SortFn<B> = Box<dyn Fn(&mut B) + Send>;
+type ProgressFn = Box<dyn Fn(usize) + Send>;
+type PriorityFn<T> = Arc<dyn Fn(&T) -> Priority + Send + Sync>;
+type RunFn<T> = Box<dyn FnMut(&T) -> bool + Send>;
+type ScheduleFn = Box<dyn FnMut(u64) -> usize + Send>;
+type Validation = Box<dyn Future<Item = bool, Error = BoxError> + Send>;
+type ValidateFn<B> = Box<dyn FnMut(&B) -> Validation + Send>;
+type BoxError = Box<dyn error::Error + Send + Sync>;
+type FlushFuture = Box<dyn Future<Item = (), Error = BoxError> + Send>;
+type FlushFutureFn = Box<dyn FnMut() -> FlushFuture + Send>;
+
+/// A `Chunks` emitting its batches as `Arc<[T]>`, see `Chunks::shared`.
+pub type SharedChunks<S> =
+    Map<Chunks<S>, fn(Vec<<S as Stream>::Item>) -> Arc<[<S as Stream>::Item]>>;
+
+/// The channel receiving the batches of a spawned `Chunks`, see
+/// `Chunks::spawn`.
+#[cfg(feature = "timer")]
+pub type BatchReceiver<S> =
+    mpsc::Receiver<Result<Vec<<S as Stream>::Item>, Error<<S as Stream>::Error>>>;
+
 /// Error returned by `Chunks`.
 #[derive(Debug)]
-pub struct Error<T>(Kind<T>);
+pub struct Error<T> {
+    kind: Kind<T>,
+    label: Option<&'static str>,
+}
 
 /// Chunks error variants
 #[derive(Debug)]
@@ -40,236 +562,4982 @@ enum Kind<T> {
 
     /// Timer returned an error.
     Timer(timer::Error),
+
+    /// Weight function returned an error.
+    Weight(BoxError),
+
+    /// Validation of a batch returned an error.
+    Validation(BoxError),
+
+    /// A future of `Chunks::flush_when` returned an error.
+    Trigger(BoxError),
+
+    /// The transform of `Chunks::map_ok_batch` returned an error.
+    Map(BoxError),
 }
 
-impl<S> Chunks<S>
-where
-    S: Stream,
-{
-    pub fn new(s: S, capacity: usize, duration: Duration) -> Chunks<S> {
-        assert!(capacity > 0);
+impl<T> Error<T> {
+    #[cfg(feature = "timer")]
+    fn new(kind: Kind<T>) -> Error<T> {
+        Error { kind, label: None }
+    }
 
-        Chunks {
-            clock: None,
-            duration,
-            items: Vec::with_capacity(capacity),
-            err: None,
-            stream: s.fuse(),
-        }
+    /// Returns the label of the `Chunks` that returned this error, see
+    /// `Chunks::label`.
+    pub fn label(&self) -> Option<&'static str> {
+        self.label
     }
 
-    fn take(&mut self) -> Vec<S::Item> {
-        let cap = self.items.capacity();
-        mem::replace(&mut self.items, Vec::with_capacity(cap))
+    /// Returns `true` if the error was caused by the inner stream.
+    pub fn is_inner(&self) -> bool {
+        matches!(self.kind, Kind::Inner(_))
     }
 
-    /// Acquires a reference to the underlying stream that this combinator is
-    /// pulling from.
-    pub fn get_ref(&self) -> &S {
-        self.stream.get_ref()
+    /// Consumes `self`, returning the error of the inner stream, if any.
+    pub fn into_inner(self) -> Option<T> {
+        match self.kind {
+            Kind::Inner(err) => Some(err),
+            _ => None,
+        }
     }
 
-    /// Acquires a mutable reference to the underlying stream that this
-    /// combinator is pulling from.
-    ///
-    /// Note that care must be taken to avoid tampering with the state of the
-    /// stream which may otherwise confuse this combinator.
-    pub fn get_mut(&mut self) -> &mut S {
-        self.stream.get_mut()
+    /// Returns `true` if the error was caused by the timer.
+    pub fn is_timer(&self) -> bool {
+        matches!(self.kind, Kind::Timer(_))
     }
 
-    /// Consumes this combinator, returning the underlying stream.
-    ///
-    /// Note that this may discard intermediate state of this combinator, so
-    /// care should be taken to avoid losing resources when this is called.
-    pub fn into_inner(self) -> S {
-        self.stream.into_inner()
+    /// Consumes `self`, returning the error of the timer, if any.
+    pub fn into_timer(self) -> Option<timer::Error> {
+        match self.kind {
+            Kind::Timer(err) => Some(err),
+            _ => None,
+        }
     }
 
-    fn flush(&mut self) -> Poll<Option<Vec<S::Item>>, S::Error> {
-        self.clock = None;
-        Ok(Some(self.take()).into())
+    /// Returns `true` if the error was returned by the weight function of
+    /// `Chunks::try_weighted`.
+    pub fn is_weight(&self) -> bool {
+        matches!(self.kind, Kind::Weight(_))
     }
-}
 
-impl<S> Stream for Chunks<S>
-where
-    S: Stream,
-{
-    type Item = Vec<<S as Stream>::Item>;
-    type Error = Error<S::Error>;
+    /// Consumes `self`, returning the error of the weight function, if any.
+    pub fn into_weight(self) -> Option<BoxError> {
+        match self.kind {
+            Kind::Weight(err) => Some(err),
+            _ => None,
+        }
+    }
 
-    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        if let Some(e) = self.err.take() {
-            return Err(e);
+    /// Returns `true` if the error was returned by the validation of
+    /// `Chunks::validate`.
+    pub fn is_validation(&self) -> bool {
+        matches!(self.kind, Kind::Validation(_))
+    }
+
+    /// Consumes `self`, returning the error of the validation, if any.
+    pub fn into_validation(self) -> Option<BoxError> {
+        match self.kind {
+            Kind::Validation(err) => Some(err),
+            _ => None,
         }
+    }
 
-        let cap = self.items.capacity();
-        loop {
-            match self.stream.poll() {
-                Ok(Async::NotReady) => {}
+    /// Returns `true` if the error was returned by a future of
+    /// `Chunks::flush_when`.
+    pub fn is_trigger(&self) -> bool {
+        matches!(self.kind, Kind::Trigger(_))
+    }
 
-                // Push the item into the buffer and check whether it is full.
-                // If so, replace our buffer with a new and empty one and return
-                // the full one.
-                Ok(Async::Ready(Some(item))) => {
-                    if self.items.is_empty() {
-                        self.clock = Some(Delay::new(Instant::now() + self.duration));
-                    }
-                    self.items.push(item);
-                    if self.items.len() >= cap {
-                        return self.flush().map_err(|e| Error(Kind::Inner(e)));
-                    } else {
-                        continue;
-                    }
-                }
+    /// Consumes `self`, returning the error of the flush trigger, if any.
+    pub fn into_trigger(self) -> Option<BoxError> {
+        match self.kind {
+            Kind::Trigger(err) => Some(err),
+            _ => None,
+        }
+    }
 
-                // Since the underlying stream ran out of values, return what we
-                // have buffered, if we have anything.
-                Ok(Async::Ready(None)) => {
-                    return if !self.items.is_empty() {
-                        let full_buf = mem::replace(&mut self.items, Vec::new());
-                        Ok(Some(full_buf).into())
-                    } else {
-                        Ok(Async::Ready(None))
-                    };
-                }
+    /// Returns `true` if the error was returned by the transform of
+    /// `Chunks::map_ok_batch`.
+    pub fn is_map(&self) -> bool {
+        matches!(self.kind, Kind::Map(_))
+    }
 
-                // If we've got buffered items be sure to return them first,
-                // we'll defer our error for later.
-                Err(e) => {
-                    if self.items.is_empty() {
-                        return Err(Error(Kind::Inner(e)));
-                    } else {
-                        self.err = Some(Error(Kind::Inner(e)));
-                        return self.flush().map_err(|e| Error(Kind::Inner(e)));
-                    }
-                }
-            }
+    /// Consumes `self`, returning the error of the batch transform, if any.
+    pub fn into_map(self) -> Option<BoxError> {
+        match self.kind {
+            Kind::Map(err) => Some(err),
+            _ => None,
+        }
+    }
 
-            match self.clock.poll() {
-                Ok(Async::Ready(Some(()))) => {
-                    return self.flush().map_err(|e| Error(Kind::Inner(e)));
-                }
-                Ok(Async::Ready(None)) => {
-                    assert!(self.items.is_empty(), "no clock but there are items");
-                }
-                Ok(Async::NotReady) => {}
-                Err(e) => {
-                    if self.items.is_empty() {
-                        return Err(Error(Kind::Timer(e)));
-                    } else {
-                        self.err = Some(Error(Kind::Timer(e)));
-                        return self.flush().map_err(|e| Error(Kind::Inner(e)));
-                    }
-                }
+    /// Consumes `self`, returning the error of the inner stream.
+    ///
+    /// This is for code that treats a failing timer as a bug, which saves
+    /// matching on an error that cannot happen.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the error was not caused by the inner stream,
+    /// i.e. if it is a timer error or an error of the weight function, the
+    /// validation, the flush trigger or the batch transform.
+    pub fn unwrap_inner(self) -> T {
+        match self.kind {
+            Kind::Inner(err) => err,
+            Kind::Timer(err) => panic!("called `unwrap_inner` on a timer error: {}", err),
+            Kind::Weight(err) => panic!("called `unwrap_inner` on a weight error: {}", err),
+            Kind::Validation(err) => {
+                panic!("called `unwrap_inner` on a validation error: {}", err)
             }
+            Kind::Trigger(err) => panic!("called `unwrap_inner` on a trigger error: {}", err),
+            Kind::Map(err) => panic!("called `unwrap_inner` on a map error: {}", err),
+        }
+    }
+}
 
-            return Ok(Async::NotReady);
+impl<T: fmt::Display> fmt::Display for Error<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(label) = self.label {
+            write!(f, "{}: ", label)?;
+        }
+        match self.kind {
+            Kind::Inner(ref err) => err.fmt(f),
+            Kind::Timer(ref err) => write!(f, "timer error: {}", err),
+            Kind::Weight(ref err) => write!(f, "weight error: {}", err),
+            Kind::Validation(ref err) => write!(f, "validation error: {}", err),
+            Kind::Trigger(ref err) => write!(f, "trigger error: {}", err),
+            Kind::Map(ref err) => write!(f, "map error: {}", err),
         }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use futures::stream;
-    use std::io;
-    use std::iter;
-    use std::time::{Duration, Instant};
+/// Error returned when a `Chunks` is configured with invalid parameters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    /// The capacity of a batch must be greater than zero.
+    InvalidCapacity(usize),
 
-    #[test]
-    fn messages_pass_through() {
-        let iter = iter::once(5);
-        let stream = stream::iter_ok::<_, io::Error>(iter);
+    /// The pull rate must allow at least one item in a non-empty period.
+    InvalidPullRate { max_items: usize, per: Duration },
 
-        let chunk_stream = Chunks::new(stream, 5, Duration::new(10, 0));
+    /// The step of `GrowthPolicy::FixedStep` must be greater than zero.
+    InvalidGrowthStep(usize),
 
-        let v = chunk_stream.collect();
-        tokio::run(v.then(|res| {
-            match res {
-                Err(_) => assert!(false),
-                Ok(v) => assert_eq!(vec![vec![5]], v),
-            };
-            Ok(())
-        }));
-    }
+    /// An option contradicts the capacity, e.g. a minimum batch size above
+    /// it. `field` names the option.
+    Inconsistent { field: &'static str, detail: String },
+}
 
-    #[test]
-    fn message_chunks() {
-        let iter = vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9].into_iter();
-        let stream = stream::iter_ok::<_, io::Error>(iter);
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BuildError::InvalidCapacity(capacity) => {
+                write!(f, "capacity must be > 0, got {}", capacity)
+            }
+            BuildError::InvalidPullRate { max_items, per } => write!(
+                f,
+                "pull rate must allow at least one item per non-empty period, got {} per {:?}",
+                max_items, per
+            ),
+            BuildError::InvalidGrowthStep(step) => {
+                write!(f, "growth step must be > 0, got {}", step)
+            }
+            BuildError::Inconsistent { field, ref detail } => {
+                write!(f, "inconsistent `{}`: {}", field, detail)
+            }
+        }
+    }
+}
 
-        let chunk_stream = Chunks::new(stream, 5, Duration::new(10, 0));
+impl error::Error for BuildError {}
 
-        let v = chunk_stream.collect();
-        tokio::run(v.then(|res| {
-            match res {
-                Err(_) => assert!(false),
-                Ok(v) => assert_eq!(vec![vec![0, 1, 2, 3, 4], vec![5, 6, 7, 8, 9]], v),
-            };
-            Ok(())
-        }));
+/// Checks the options that are bounded by the capacity: the sizes in
+/// `minimums` must not exceed it, and the `ceiling` must not be below it.
+/// Unset options are skipped.
+pub(crate) fn check_bounds(
+    capacity: usize,
+    minimums: &[(&'static str, Option<usize>)],
+    ceiling: (&'static str, Option<usize>),
+) -> Result<(), BuildError> {
+    for &(field, min) in minimums {
+        match min {
+            Some(min) if min > capacity => {
+                return Err(BuildError::Inconsistent {
+                    field,
+                    detail: format!("{} exceeds the capacity {}", min, capacity),
+                })
+            }
+            _ => {}
+        }
+    }
+    match ceiling {
+        (field, Some(cap)) if cap < capacity => Err(BuildError::Inconsistent {
+            field,
+            detail: format!("{} is below the capacity {}", cap, capacity),
+        }),
+        _ => Ok(()),
+    }
+}
+
+impl<S> Chunks<S>
+where
+    S: Stream,
+{
+    #[cfg(feature = "timer")]
+    #[track_caller]
+    pub fn new(s: S, capacity: usize, duration: Duration) -> Chunks<S> {
+        match Chunks::try_new(s, capacity, duration) {
+            Ok(chunks) => chunks,
+            Err(e) => panic!("Chunks {}", e),
+        }
+    }
+
+    /// Creates a new `Chunks` which only flushes full batches and the final
+    /// batch, without any timeout.
+    ///
+    /// This is the same as `FlushMode::CapacityOnly` and the only kind of
+    /// batching that is available without the `timer` feature.
+    #[track_caller]
+    pub fn with_capacity(s: S, capacity: usize) -> Chunks<S> {
+        let mut chunks = match Chunks::with_inner(Inner::fused(s), capacity, Duration::new(0, 0)) {
+            Ok(chunks) => chunks,
+            Err(e) => panic!("Chunks {}", e),
+        };
+        chunks.set_mode(FlushMode::CapacityOnly);
+        chunks
+    }
+
+    /// Creates a new `Chunks` which flushes full batches, and additionally
+    /// flushes on every tick of a fixed `interval`.
+    ///
+    /// A tick flushes whatever was buffered, emitting an empty batch if
+    /// nothing was, so the consumer gets a regular heartbeat. The ticks start
+    /// with the first poll and keep their schedule regardless of the batches
+    /// flushed by the capacity in between. They end with the inner stream.
+    ///
+    /// Ticks are reported as `FlushReason::Heartbeat`, are not counted as
+    /// timeouts and are never padded by `pad_to_capacity`.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `capacity` or `interval` is zero.
+    #[cfg(feature = "timer")]
+    #[track_caller]
+    pub fn with_capacity_and_interval(s: S, capacity: usize, interval: Duration) -> Chunks<S> {
+        assert!(
+            interval > Duration::new(0, 0),
+            "Chunks interval must be > 0"
+        );
+
+        let mut chunks = Chunks::with_capacity(s, capacity);
+        chunks.heartbeat = Some((interval, None));
+        chunks
+    }
+
+    /// Creates a new `Chunks` with a timeout of `millis` milliseconds.
+    ///
+    /// This is a shorthand for `Chunks::new` with `Duration::from_millis`.
+    #[cfg(feature = "timer")]
+    #[track_caller]
+    pub fn new_ms(s: S, capacity: usize, millis: u64) -> Chunks<S> {
+        Chunks::new(s, capacity, Duration::from_millis(millis))
+    }
+
+    /// Creates a new `Chunks`, failing instead of panicking if the capacity
+    /// is zero.
+    #[cfg(feature = "timer")]
+    pub fn try_new(s: S, capacity: usize, duration: Duration) -> Result<Chunks<S>, BuildError> {
+        Chunks::with_inner(Inner::fused(s), capacity, duration)
+    }
+
+    /// Creates a new `Chunks` from `config`, failing if its parameters are
+    /// invalid.
+    #[cfg(feature = "timer")]
+    pub fn from_config(s: S, config: &ChunksConfig) -> Result<Chunks<S>, BuildError> {
+        config.validate()?;
+        let mut chunks =
+            Chunks::try_new(s, config.capacity, Duration::from_millis(config.timeout_ms))?;
+        if let Some(min_size) = config.min_size {
+            chunks = chunks.soft_flush_at(min_size);
+        }
+        if let Some(min_tail) = config.min_tail {
+            chunks = chunks.absorb_small_tail(min_tail);
+        }
+        if let Some(hard_cap) = config.hard_cap {
+            chunks = chunks.hard_cap(hard_cap);
+        }
+        Ok(chunks)
+    }
+
+    /// Creates a new `Chunks` whose batches only contain distinct items.
+    ///
+    /// Items that are already part of the current batch are dropped, so the
+    /// capacity counts distinct items. The first occurrence of an item keeps
+    /// its position in the batch.
+    #[cfg(feature = "timer")]
+    #[track_caller]
+    pub fn distinct(s: S, capacity: usize, duration: Duration) -> Chunks<S>
+    where
+        S::Item: Hash + Eq + Clone + Send + 'static,
+    {
+        let mut chunks = Chunks::new(s, capacity, duration);
+        chunks.filter = Some(Box::new(Distinct {
+            seen: HashSet::new(),
+        }));
+        chunks
+    }
+
+    /// Creates a new `Chunks` whose timeouts are extended by a random offset
+    /// of up to `jitter`.
+    ///
+    /// The offset is drawn from `rng` once per batch, which spreads out the
+    /// flushes of many batchers that were started at the same time.
+    #[cfg(feature = "timer")]
+    #[track_caller]
+    pub fn new_with_rng<R>(
+        s: S,
+        capacity: usize,
+        duration: Duration,
+        jitter: Duration,
+        rng: R,
+    ) -> Chunks<S>
+    where
+        R: NextU64 + 'static,
+    {
+        let mut chunks = Chunks::new(s, capacity, duration);
+        chunks.jitter = Some(Jitter {
+            max: jitter,
+            rng: Box::new(rng),
+        });
+        chunks
+    }
+
+    /// Creates a new `Chunks` which flushes a batch once the weights of its
+    /// items add up to at least `max_weight`.
+    ///
+    /// This is useful to bound batches by their size in bytes rather than
+    /// by the number of items. An item that would take a non-empty batch
+    /// beyond `max_weight` is held back for the next batch, so only a single
+    /// item heavier than `max_weight` forms a batch that exceeds it.
+    #[cfg(feature = "timer")]
+    #[track_caller]
+    pub fn weighted<F>(s: S, max_weight: usize, duration: Duration, weight_fn: F) -> Chunks<S>
+    where
+        F: Fn(&S::Item) -> usize + Send + 'static,
+    {
+        Chunks::try_weighted(s, max_weight, duration, move |item| {
+            Ok::<_, Infallible>(weight_fn(item))
+        })
+    }
+
+    /// Like `weighted`, but the weight of an item may fail to be computed.
+    ///
+    /// An item whose weight fails is dropped. The current batch is flushed
+    /// and the error is returned after it, see `Error::is_weight`.
+    #[cfg(feature = "timer")]
+    #[track_caller]
+    pub fn try_weighted<F, E>(
+        s: S,
+        max_weight: usize,
+        duration: Duration,
+        weight_fn: F,
+    ) -> Chunks<S>
+    where
+        F: Fn(&S::Item) -> Result<usize, E> + Send + 'static,
+        E: Into<BoxError>,
+    {
+        assert!(max_weight > 0, "Chunks max weight must be > 0");
+
+        // Only the weight limits a batch, so let the buffer grow on demand
+        // instead of preallocating an unbounded capacity.
+        let mut chunks = Chunks::new(s, 1, duration).growth_policy(GrowthPolicy::Doubling);
+        chunks.capacity = usize::MAX;
+        chunks.weight = Some(Weight {
+            weigh: Box::new(move |item| weight_fn(item).map_err(Into::into)),
+            target: max_weight,
+            max: max_weight,
+            used: 0,
+        });
+        chunks
+    }
+
+    /// Aims for batches of a total weight of `target`, give or take
+    /// `tolerance`, instead of the `max_weight` of `weighted`.
+    ///
+    /// A batch is flushed once its weight reaches `target`. An item that
+    /// would take it beyond `target + tolerance` is held back for the next
+    /// batch instead, so batches may overshoot the target a little rather
+    /// than being flushed well below it. As with `weighted`, an item heavier
+    /// than `target + tolerance` forms a batch on its own.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `target` is zero or if this combinator was not
+    /// created by `weighted` or `try_weighted`.
+    #[cfg(feature = "timer")]
+    pub fn weight_target(mut self, target: usize, tolerance: usize) -> Chunks<S> {
+        assert!(target > 0, "Chunks weight target must be > 0");
+
+        let weight = self
+            .weight
+            .as_mut()
+            .expect("weight_target requires a weighted Chunks");
+        weight.target = target;
+        weight.max = target.saturating_add(tolerance);
+        self
+    }
+
+    /// Creates a new `Chunks` together with a handle to pause and resume
+    /// pulling from `s`.
+    #[cfg(feature = "timer")]
+    #[track_caller]
+    pub fn pausable(s: S, capacity: usize, duration: Duration) -> (Chunks<S>, PauseHandle) {
+        let handle = PauseHandle::new();
+        let mut chunks = Chunks::new(s, capacity, duration);
+        chunks.pause = Some(handle.clone());
+        (chunks, handle)
+    }
+
+    /// Creates a new `Chunks` which polls `s` directly instead of fusing it.
+    ///
+    /// This saves a branch on every poll of the inner stream for hot paths.
+    /// The combinator itself never polls `s` after it returned `Ready(None)`,
+    /// but the caller must not do so either through `get_mut`, as the stream
+    /// is not protected against it anymore. Debug builds panic if the inner
+    /// stream is polled after completion.
+    #[cfg(feature = "timer")]
+    #[track_caller]
+    pub fn new_unchecked(s: S, capacity: usize, duration: Duration) -> Chunks<S> {
+        match Chunks::with_inner(Inner::unfused(s), capacity, duration) {
+            Ok(chunks) => chunks,
+            Err(e) => panic!("Chunks {}", e),
+        }
+    }
+
+    /// Sorts every batch by the key computed by `f` before it is emitted.
+    ///
+    /// The sort is stable, so items with equal keys keep their arrival
+    /// order. It costs `O(n log n)` per batch of `n` items.
+    pub fn sort_by_key<K, F>(mut self, f: F) -> Chunks<S>
+    where
+        K: Ord,
+        F: Fn(&S::Item) -> K + Send + 'static,
+    {
+        self.sort = Some(Box::new(move |batch| batch.sort_by_key(&f)));
+        self
+    }
+
+    /// Emits the items that `f` deems `Priority::High` at the front of their
+    /// batch, followed by the `Priority::Low` ones.
+    ///
+    /// Both lanes keep their arrival order, and the items are counted
+    /// together against the capacity and the other limits. The lanes are
+    /// split up after `sort_by_key`, so each of them stays sorted. See
+    /// `high_priority_timeout` to also flush high priority items sooner.
+    pub fn prioritize<F>(mut self, f: F) -> Chunks<S>
+    where
+        F: Fn(&S::Item) -> Priority + Send + Sync + 'static,
+    {
+        let classify = Arc::new(f);
+        let lanes = classify.clone();
+        let partition: SortFn<Vec<S::Item>> = Box::new(move |batch| {
+            let (mut high, low): (Vec<_>, Vec<_>) = batch
+                .drain(..)
+                .partition(|item| lanes(item) == Priority::High);
+            high.extend(low);
+            *batch = high;
+        });
+        self.priority = Some((classify, partition));
+        self
+    }
+
+    /// Pads every batch that is shorter than the capacity with
+    /// `Default::default()` values.
+    ///
+    /// This suits fixed-width downstream formats. The number of real items in
+    /// the last emitted batch is available from `unpadded_len`. Batches
+    /// flushed by a tick of `with_capacity_and_interval` are left as they
+    /// are, so an empty heartbeat stays empty.
+    pub fn pad_to_capacity(mut self) -> Chunks<S>
+    where
+        S::Item: Default,
+    {
+        self.pad = Some(pad_with_default::<S::Item>);
+        self
+    }
+
+    /// Emits every batch as an `Arc<[T]>` which can be shared by many
+    /// consumers without cloning the items.
+    ///
+    /// Converting a batch moves its items into a new allocation once, which
+    /// costs about as much as cloning the `Vec` a single time.
+    pub fn shared(self) -> SharedChunks<S> {
+        Stream::map(self, Arc::from)
+    }
+
+    /// Forwards every batch into `sink`, pulling the next batch only once the
+    /// sink accepted the previous one.
+    ///
+    /// The returned future completes with this combinator and the sink once
+    /// the inner stream ended and all batches were flushed into the sink.
+    /// Errors of the stream and of the sink are told apart by `DriveError`.
+    pub fn forward_batches<K>(self, sink: K) -> Forward<S, K>
+    where
+        K: Sink<SinkItem = Vec<S::Item>>,
+    {
+        Forward::new(self, sink)
+    }
+
+    /// Forwards every batch into `sink` like `forward_batches`, but flushes
+    /// the current batch early whenever the sink is ready for more.
+    ///
+    /// This trades larger batches for freshness: a sink that keeps up gets
+    /// every item as soon as the inner stream went idle, while a busy sink
+    /// lets the items pile up into full batches. The early flushes are
+    /// requested through the `FlushHandle` of this combinator.
+    pub fn forward_on_ready<K>(self, sink: K) -> ForwardOnReady<S, K>
+    where
+        K: Sink<SinkItem = Vec<S::Item>>,
+    {
+        ForwardOnReady::new(self, sink)
+    }
+
+    /// Calls `f` with every batch, waiting for the future it returns before
+    /// pulling the next batch.
+    ///
+    /// This is a push style alternative to consuming the batches as a stream.
+    /// Errors of the stream and of the processing are told apart by
+    /// `DriveError`.
+    pub fn for_each_batch<F, U>(self, f: F) -> ForEachBatch<S, F, U>
+    where
+        F: FnMut(Vec<S::Item>) -> U,
+        U: IntoFuture<Item = ()>,
+    {
+        ForEachBatch::new(self, f)
+    }
+
+    /// Folds every batch into an accumulator with `f`, waiting for the future
+    /// it returns before pulling the next batch.
+    ///
+    /// The returned future completes with the final accumulator once the
+    /// inner stream ended. Errors of the stream and of the fold are told
+    /// apart by `DriveError`.
+    pub fn try_fold_batches<A, F, U>(self, init: A, f: F) -> TryFoldBatches<S, F, U, A>
+    where
+        F: FnMut(A, Vec<S::Item>) -> U,
+        U: IntoFuture<Item = A>,
+    {
+        TryFoldBatches::new(self, init, f)
+    }
+
+    /// Spawns a task on the default executor which drives this combinator,
+    /// and returns a channel receiving its batches.
+    ///
+    /// The channel is bounded, so the task stops pulling items while the
+    /// receiver lags behind. An error is sent into the channel like a batch and
+    /// the task keeps polling afterwards, as the stream may continue. The task
+    /// ends once the inner stream ended or the receiver was dropped.
+    ///
+    /// This must be called from within a tokio runtime.
+    #[cfg(feature = "timer")]
+    pub fn spawn(self) -> BatchReceiver<S>
+    where
+        S: Send + 'static,
+        S::Item: Send,
+        S::Error: Send,
+    {
+        let (tx, rx) = mpsc::channel(1);
+        let forward = self
+            .then(Ok::<_, ()>)
+            .forward(tx.sink_map_err(|_| ()))
+            .map(|_| ());
+        tokio::spawn(forward);
+        rx
+    }
+
+    /// Polls for the next batch together with why it was flushed and how long
+    /// it was collected.
+    ///
+    /// The metadata belongs to the returned batch, also if it was held back
+    /// by `absorb_small_tail`. Should it not be known, the batch is reported
+    /// with `FlushReason::End` and a zero age.
+    pub fn poll_batch_info(&mut self) -> Poll<Option<BatchInfo<S::Item>>, Error<S::Error>> {
+        let items = match self.poll()? {
+            Async::Ready(Some(items)) => items,
+            Async::Ready(None) => return Ok(Async::Ready(None)),
+            Async::NotReady => return Ok(Async::NotReady),
+        };
+        let age = self
+            .flush_times()
+            .map_or(Duration::new(0, 0), |(first_item_at, emitted_at)| {
+                emitted_at - first_item_at
+            });
+        Ok(Async::Ready(Some(BatchInfo {
+            items,
+            reason: self.last_reason.unwrap_or(FlushReason::End),
+            age,
+        })))
+    }
+
+    /// Validates every batch with `f` before it is emitted.
+    ///
+    /// The future returned by `f` is driven to completion before the batch is
+    /// emitted, e.g. to check the batch against a remote schema. A batch that
+    /// is rejected is dropped or buffered again, depending on `on_reject`.
+    /// Buffered again, its items are emitted, and validated, together with
+    /// the next batch, even after the inner stream ended; a batch that is
+    /// always rejected is never emitted. An error of the validation drops the
+    /// batch and is returned by `poll`.
+    pub fn validate<F, U>(mut self, f: F, on_reject: OnReject) -> Chunks<S>
+    where
+        F: Fn(&[S::Item]) -> U + Send + 'static,
+        U: IntoFuture<Item = bool>,
+        U::Future: Send + 'static,
+        U::Error: Into<BoxError> + 'static,
+    {
+        let validate = move |batch: &Vec<S::Item>| -> Validation {
+            Box::new(f(batch).into_future().map_err(Into::into))
+        };
+        self.validate = Some((Box::new(validate), on_reject));
+        self
+    }
+
+    /// Pairs every batch with the highest offset of its items.
+    pub fn with_offsets(self) -> OffsetChunks<S>
+    where
+        S::Item: HasOffset,
+    {
+        OffsetChunks::new(self)
+    }
+
+    /// Tags every batch with the time its first item arrived and the time it
+    /// was flushed.
+    pub fn timestamped(self) -> Timestamped<S> {
+        Timestamped::new(self)
+    }
+
+    /// Only emits the most recent batch whenever the consumer polls, dropping
+    /// the older batches that completed in the meantime.
+    ///
+    /// See `LatestBatch` for the data loss this implies.
+    pub fn latest_batch(self) -> LatestBatch<S> {
+        LatestBatch::new(self)
+    }
+
+    /// Collects the batches into buffers from `pool`, which are returned to
+    /// the pool once the emitted batches are dropped.
+    pub fn pooled(self, pool: BufferPool<S::Item>) -> PooledChunks<S> {
+        PooledChunks::new(self, pool)
+    }
+
+    /// Stops batching, returning a stream of the buffered items followed by
+    /// the remaining items of the inner stream, one by one.
+    ///
+    /// This is an escape hatch for shutdown or error handling, e.g. to hand
+    /// every item to a fallback that processes them individually. No items
+    /// are lost, and an error of the inner stream that was deferred behind
+    /// the buffered items is returned after them. The other deferred errors,
+    /// such as those of the timer, are dropped.
+    pub fn drain_items(self) -> DrainItems<S> {
+        DrainItems::new(self)
+    }
+
+    /// Transforms every batch with `f`, returning its errors through the
+    /// error type of this combinator.
+    ///
+    /// This suits fallible steps such as serializing or compressing a batch,
+    /// whose errors are then told apart by `Error::is_map`.
+    pub fn map_ok_batch<F, U, E>(self, f: F) -> MapOkBatch<S, F>
+    where
+        F: FnMut(Vec<S::Item>) -> Result<U, E>,
+        E: Into<BoxError>,
+    {
+        MapOkBatch::new(self, f)
+    }
+}
+
+impl<S, B> Chunks<S, B>
+where
+    S: Stream,
+    B: Batch<S::Item>,
+{
+    /// Creates a new `Chunks` which collects its batches into a `B` instead
+    /// of a `Vec`.
+    ///
+    /// The options that need to reorder the items of a batch, such as
+    /// `sort_by_key` and `pad_to_capacity`, are only available for `Vec`.
+    ///
+    /// ```
+    /// use std::collections::VecDeque;
+    /// use std::io;
+    /// use std::time::Duration;
+    ///
+    /// use futures::{stream, Stream};
+    /// use tokio::runtime::current_thread;
+    /// use tokio_batch::Chunks;
+    ///
+    /// let stream = stream::iter_ok::<_, io::Error>(vec![1, 2, 3]);
+    /// let chunk_stream = Chunks::<_, VecDeque<_>>::with_batch(stream, 2, Duration::new(10, 0));
+    ///
+    /// let v = current_thread::block_on_all(chunk_stream.collect()).unwrap();
+    /// assert_eq!(v, vec![VecDeque::from(vec![1, 2]), VecDeque::from(vec![3])]);
+    /// ```
+    #[cfg(feature = "timer")]
+    #[track_caller]
+    pub fn with_batch(s: S, capacity: usize, duration: Duration) -> Chunks<S, B> {
+        match Chunks::with_inner(Inner::fused(s), capacity, duration) {
+            Ok(chunks) => chunks,
+            Err(e) => panic!("Chunks {}", e),
+        }
+    }
+
+    fn with_inner(
+        stream: Inner<S>,
+        capacity: usize,
+        duration: Duration,
+    ) -> Result<Chunks<S, B>, BuildError> {
+        if capacity == 0 {
+            return Err(BuildError::InvalidCapacity(capacity));
+        }
+
+        Ok(Chunks {
+            clock: None,
+            duration,
+            capacity,
+            hard_cap: None,
+            #[cfg(feature = "metrics")]
+            gaps: GapRecorder::default(),
+            growth_policy: GrowthPolicy::Preallocate,
+            schedule: None,
+            items: B::with_capacity(capacity.min(MAX_PREALLOCATE)),
+            err: None,
+            stream,
+            item_timeout: None,
+            jitter: None,
+            rate_limit: None,
+            pause: None,
+            flush_handle: None,
+            pressure: None,
+            flush_when: None,
+            last_reason: None,
+            memory: None,
+            weight: None,
+            grace: None,
+            filter: None,
+            boundary: None,
+            runs: None,
+            shutdown: None,
+            ignore_shutdown_cancel: false,
+            completing: false,
+            done: false,
+            absorb_small_tail: None,
+            held: None,
+            taken: Usage::default(),
+            created_at: clock::now(),
+            started_at: None,
+            flushed_at: None,
+            retry_inner: None,
+            hold_on_error: false,
+            inner_failures: 0,
+            carried: None,
+            counters: Counters::default(),
+            sort: None,
+            priority: None,
+            priority_timeout: None,
+            inspect: None,
+            progress: None,
+            validate: None,
+            validating: None,
+            pad: None,
+            unpadded_len: 0,
+            yield_after: None,
+            ready_streak: 0,
+            soft_flush_at: None,
+            timeout_cycle: None,
+            catchup: None,
+            first_deadline: None,
+            min_latency: None,
+            floor: None,
+            mode: FlushMode::Hybrid,
+            tick: None,
+            heartbeat: None,
+            missed_tick: MissedTickBehavior::Burst,
+            timer_policy: TimerPolicy::Reset,
+            label: None,
+        })
+    }
+
+    /// Computes the timeout of every item individually.
+    ///
+    /// Instead of using the same duration for every batch, the deadline of a
+    /// batch becomes the earliest deadline among its buffered items. When an
+    /// item with a tighter budget arrives later on, the timer is re-armed so
+    /// that the whole batch is flushed earlier.
+    #[cfg(feature = "timer")]
+    pub fn item_timeout<F>(mut self, f: F) -> Chunks<S, B>
+    where
+        F: Fn(&S::Item) -> Duration + Send + 'static,
+    {
+        self.item_timeout = Some(Box::new(f));
+        self
+    }
+
+    /// Gives every item a time to live of `ttl`, flushing a batch once its
+    /// oldest item expires.
+    ///
+    /// This is `item_timeout` with the same timeout for every item: the
+    /// deadline is set by the first item of a batch and later items never
+    /// extend it, so no item waits longer than `ttl` to be emitted. Options
+    /// that hold a batch back on purpose, such as `grace`, `min_latency` or
+    /// `OnReject::Rebuffer`, can still delay an item past its time to live.
+    #[cfg(feature = "timer")]
+    pub fn ttl(self, ttl: Duration) -> Chunks<S, B> {
+        self.item_timeout(move |_| ttl)
+    }
+
+    /// Limits how fast items are pulled from the inner stream.
+    ///
+    /// No more than `max_items` are pulled within any window of length `per`.
+    /// Once the limit is reached the combinator stops polling the inner stream
+    /// until enough time has passed, batches are still emitted by capacity
+    /// and timeout from what was pulled so far.
+    #[cfg(feature = "timer")]
+    pub fn pull_rate_limit(self, max_items: usize, per: Duration) -> Chunks<S, B> {
+        self.try_pull_rate_limit(max_items, per)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like `pull_rate_limit`, but fails instead of panicking if the rate
+    /// does not allow any items to be pulled.
+    #[cfg(feature = "timer")]
+    pub fn try_pull_rate_limit(
+        mut self,
+        max_items: usize,
+        per: Duration,
+    ) -> Result<Chunks<S, B>, BuildError> {
+        if max_items == 0 || per == Duration::new(0, 0) {
+            return Err(BuildError::InvalidPullRate { max_items, per });
+        }
+
+        self.rate_limit = Some(RateLimit::new(max_items, per));
+        Ok(self)
+    }
+
+    /// Flushes the current batch once the buffered items take up
+    /// `max_memory`.
+    ///
+    /// The size of every item is estimated by `size_of`. As the size of the
+    /// next item is not known before it is pulled, the last item may take the
+    /// total beyond `max_memory`. The batch is flushed right after it with
+    /// `FlushReason::Memory`, so this also bounds `Chunks::with_capacity`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `max_memory` is zero.
+    pub fn max_memory<F>(mut self, max_memory: usize, size_of: F) -> Chunks<S, B>
+    where
+        F: Fn(&S::Item) -> usize + Send + 'static,
+    {
+        assert!(max_memory > 0, "Chunks max memory must be > 0");
+
+        self.memory = Some(MemoryLimit {
+            size_of: Box::new(size_of),
+            max: max_memory,
+            used: 0,
+        });
+        self
+    }
+
+    /// Flushes the current batch when an item equal to `sentinel` arrives.
+    ///
+    /// This is useful for protocols that mark the end of a logical message
+    /// with a distinguished frame. The frame itself is either included as the
+    /// last item of the batch or dropped.
+    pub fn flush_on(mut self, sentinel: S::Item, mode: Sentinel) -> Chunks<S, B>
+    where
+        S::Item: PartialEq + Send + 'static,
+    {
+        self.boundary = Some((Box::new(move |item| *item == sentinel), mode));
+        self
+    }
+
+    /// Starts a new batch whenever the key of an item differs from the key of
+    /// the batch.
+    ///
+    /// This groups consecutive items with the same key, e.g. the records of a
+    /// time series that fall into the same minute. The item with the new key
+    /// is the first item of the next batch. The capacity and the timeout
+    /// still bound every run.
+    pub fn group_runs<F, K>(mut self, key: F) -> Chunks<S, B>
+    where
+        F: Fn(&S::Item) -> K + Send + 'static,
+        K: PartialEq + Send + 'static,
+    {
+        let mut current = None;
+        self.runs = Some(Box::new(move |item| {
+            let key = key(item);
+            let changed = current.as_ref().is_some_and(|current| *current != key);
+            current = Some(key);
+            changed
+        }));
+        self
+    }
+
+    fn is_boundary(&self, item: &S::Item) -> Option<Sentinel> {
+        match self.boundary {
+            Some((ref f, mode)) if f(item) => Some(mode),
+            _ => None,
+        }
+    }
+
+    /// Gives a batch that is almost full a chance to fill up before it is
+    /// flushed by the timer.
+    ///
+    /// If the timer fires while the batch holds at least `threshold_fraction`
+    /// of its capacity, the timer is re-armed for `extension` instead. This
+    /// happens at most once per batch, so the latency of a batch never
+    /// exceeds the timeout plus `extension`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `threshold_fraction` is not within `(0, 1]`.
+    #[cfg(feature = "timer")]
+    pub fn grace(mut self, threshold_fraction: f64, extension: Duration) -> Chunks<S, B> {
+        assert!(threshold_fraction > 0.0 && threshold_fraction <= 1.0);
+
+        self.grace = Some(Grace {
+            threshold: threshold_fraction,
+            extension,
+            extended: false,
+        });
+        self
+    }
+
+    /// Yields to the executor after `batches` batches were emitted in a row
+    /// without the inner stream ever being pending.
+    ///
+    /// A consumer usually polls again right after receiving a batch, so a
+    /// stream with a large burst of ready items would otherwise keep its
+    /// task busy until the burst is drained, starving other tasks on the same
+    /// thread. With this option the combinator returns `NotReady` once the
+    /// budget is spent and immediately schedules its task again.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `batches` is zero.
+    pub fn yield_after(mut self, batches: usize) -> Chunks<S, B> {
+        assert!(batches > 0);
+
+        self.yield_after = Some(batches);
+        self
+    }
+
+    /// Keeps polling the inner stream after up to `retries` consecutive
+    /// errors.
+    ///
+    /// This is meant for sources that fail transiently but recover on their
+    /// own. A swallowed error still flushes the partial batch, and the count
+    /// starts over with every item pulled. Once the inner stream fails more
+    /// than `retries` times in a row, its error is returned as usual after
+    /// the buffered items were emitted.
+    pub fn retry_inner(mut self, retries: usize) -> Chunks<S, B> {
+        self.retry_inner = Some(retries);
+        self
+    }
+
+    /// Calls `f` with every item as it is added to a batch.
+    ///
+    /// Unlike `Stream::inspect` on the inner stream, this only sees the items
+    /// that are actually buffered, i.e. not the ones dropped by `distinct` or
+    /// a dropped boundary of `flush_on`, and no extra combinator is needed.
+    pub fn inspect_item<F>(mut self, f: F) -> Chunks<S, B>
+    where
+        F: Fn(&S::Item) + Send + 'static,
+    {
+        self.inspect = Some(Box::new(f));
+        self
+    }
+
+    /// Calls `on_progress` with the length of the batch whenever it grows to
+    /// a multiple of `every` items.
+    ///
+    /// This reports the progress of big batches that fill up slowly, e.g. to
+    /// a UI, without handing out the items. A batch that fills up is reported
+    /// before it is emitted.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `every` is zero.
+    pub fn progress_every<F>(mut self, every: usize, on_progress: F) -> Chunks<S, B>
+    where
+        F: Fn(usize) + Send + 'static,
+    {
+        assert!(every > 0, "progress interval must be > 0");
+
+        self.progress = Some((every, Box::new(on_progress)));
+        self
+    }
+
+    /// Keeps accumulating the partial batch across the errors swallowed by
+    /// `retry_inner`, instead of flushing it.
+    ///
+    /// The items pulled before and after a transient error then land in the
+    /// same batch, which is only flushed by its regular triggers. An error
+    /// that exhausts the retries still flushes the partial batch before it is
+    /// returned. Without `retry_inner` this has no effect.
+    pub fn hold_on_error(mut self) -> Chunks<S, B> {
+        self.hold_on_error = true;
+        self
+    }
+
+    /// Flushes a batch with at least `threshold` items early once the inner
+    /// stream has no more items ready.
+    ///
+    /// This favours batches of a decent size under light load without
+    /// waiting for the full capacity or the timeout. Smaller batches are still
+    /// only flushed by the timer.
+    pub fn soft_flush_at(mut self, threshold: usize) -> Chunks<S, B> {
+        self.soft_flush_at = Some(threshold);
+        self
+    }
+
+    /// Sets the capacity of every batch from `schedule`, which is called with
+    /// the index of the batch before it is started.
+    ///
+    /// This allows to ramp up the batch size after a start or to adapt it to
+    /// the load. The capacity passed to the constructor is replaced by the
+    /// capacity that `schedule` returns for the first batch.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `schedule` returns zero for the first batch. A
+    /// zero for a later batch is only seen once that batch is started, so
+    /// polling the stream panics then.
+    pub fn capacity_schedule<F>(mut self, mut schedule: F) -> Chunks<S, B>
+    where
+        F: FnMut(u64) -> usize + Send + 'static,
+    {
+        self.capacity = schedule(0);
+        assert!(
+            self.capacity > 0,
+            "capacity schedule returned 0 for batch 0"
+        );
+        self.items = self.buffer();
+        self.schedule = Some((Box::new(schedule), 0));
+        self
+    }
+
+    /// Flushes every batch that reaches `max_items`, no matter what the other
+    /// triggers say.
+    ///
+    /// This bounds the memory of the modes whose batch size can grow, such as
+    /// `capacity_schedule` or `FlushMode::Interval`, which ignores the
+    /// capacity. A small final batch is only merged by `absorb_small_tail` if
+    /// the result stays within the cap, and `pad_to_capacity` pads up to the
+    /// cap at most. Only the items of a batch that `validate` buffered again
+    /// can exceed it.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `max_items` is zero.
+    pub fn hard_cap(mut self, max_items: usize) -> Chunks<S, B> {
+        assert!(max_items > 0, "hard cap must be > 0");
+
+        self.hard_cap = Some(max_items);
+        self
+    }
+
+    /// Flushes the first batch at `deadline` instead of `duration` after its
+    /// first item arrived.
+    ///
+    /// This aligns the schedule of a batcher with an external one, e.g. with
+    /// the start of a second or with other batchers. All later batches use
+    /// the regular `duration`. If the deadline already passed when the first
+    /// item arrives, the first batch is flushed on the next poll.
+    #[cfg(feature = "timer")]
+    pub fn first_deadline(mut self, deadline: Instant) -> Chunks<S, B> {
+        self.first_deadline = Some(deadline);
+        self
+    }
+
+    /// Uses the shorter `timeout` for the batches after a batch was flushed by
+    /// the timer, until a batch fills up again.
+    ///
+    /// After a stall the first batches tend to be small, so they are flushed
+    /// sooner to catch up with the items that pile up in the meantime. Once
+    /// the capacity or the weight limit flushes a batch, the regular timeout
+    /// is used again. This has no effect on the timeouts of `item_timeout`
+    /// and `FlushMode::Interval`.
+    #[cfg(feature = "timer")]
+    pub fn catchup_timeout(mut self, timeout: Duration) -> Chunks<S, B> {
+        self.catchup = Some((timeout, false));
+        self
+    }
+
+    /// Flushes a batch at most `timeout` after a high priority item of
+    /// `prioritize` was buffered.
+    ///
+    /// Such an item can only make the deadline of its batch earlier, never
+    /// later. This has no effect without `prioritize` or in
+    /// `FlushMode::CapacityOnly`.
+    #[cfg(feature = "timer")]
+    pub fn high_priority_timeout(mut self, timeout: Duration) -> Chunks<S, B> {
+        self.priority_timeout = Some(timeout);
+        self
+    }
+
+    /// Cycles through `timeouts` for the timer of consecutive batches.
+    ///
+    /// Every batch that arms its timer uses the next timeout and the cycle
+    /// starts over after the last one, so `&[tight, loose]` alternates between
+    /// the two. A single timeout is the same as a fixed duration.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `timeouts` is empty.
+    #[cfg(feature = "timer")]
+    pub fn timeouts(mut self, timeouts: &[Duration]) -> Chunks<S, B> {
+        assert!(!timeouts.is_empty());
+
+        self.duration = timeouts[0];
+        self.timeout_cycle = if timeouts.len() > 1 {
+            Some((timeouts.to_vec(), 0))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Flushes the buffered items as soon as the inner stream has no more
+    /// items ready.
+    ///
+    /// This is the same as `soft_flush_at(1)` and keeps the latency low under
+    /// light load. Combine it with `min_latency` to keep a stream that only
+    /// pauses briefly from turning every item into a batch of its own.
+    pub fn eager_flush(self) -> Chunks<S, B> {
+        self.soft_flush_at(1)
+    }
+
+    /// Waits at least `min_latency` after a batch was emitted before the next
+    /// batch is flushed because the inner stream went idle.
+    ///
+    /// Only the flushes of `soft_flush_at` and `eager_flush` are held back;
+    /// the capacity and the timeout flush a batch as usual.
+    #[cfg(feature = "timer")]
+    pub fn min_latency(mut self, min_latency: Duration) -> Chunks<S, B> {
+        self.min_latency = Some(min_latency);
+        self
+    }
+
+    /// Appends a final batch with fewer than `min_tail` items to the batch
+    /// before it instead of emitting it on its own.
+    ///
+    /// To know whether a batch is followed by such a tail, every batch is held
+    /// back until the next one is complete or the stream ends. This adds the
+    /// time it takes to fill the next batch to the latency of every batch.
+    pub fn absorb_small_tail(mut self, min_tail: usize) -> Chunks<S, B> {
+        self.absorb_small_tail = Some(min_tail);
+        self
+    }
+
+    /// Returns the number of items in the last emitted batch, excluding the
+    /// padding added by `pad_to_capacity`.
+    pub fn unpadded_len(&self) -> usize {
+        self.unpadded_len
+    }
+
+    /// Shuts the stream down once `signal` resolves.
+    ///
+    /// The items buffered at that point are emitted as the final batch and the
+    /// stream completes afterwards without polling the inner stream again. If
+    /// the sending half is dropped this is treated as a shutdown as well,
+    /// unless `ignore_shutdown_cancel` is set.
+    pub fn shutdown_on(mut self, signal: oneshot::Receiver<()>) -> Chunks<S, B> {
+        self.shutdown = Some(signal);
+        self
+    }
+
+    /// Keeps the stream running if the sending half of the shutdown signal is
+    /// dropped without firing.
+    pub fn ignore_shutdown_cancel(mut self) -> Chunks<S, B> {
+        self.ignore_shutdown_cancel = true;
+        self
+    }
+
+    /// Flushes the buffered items on the next poll once `flag` is set, for
+    /// example by a controller shedding memory under pressure.
+    ///
+    /// The flag is cleared when it is noticed, also if nothing is buffered.
+    /// Setting it does not wake up the task, so the flush happens on whatever
+    /// poll comes next.
+    pub fn flush_on_pressure(mut self, flag: Arc<AtomicBool>) -> Chunks<S, B> {
+        self.pressure = Some(flag);
+        self
+    }
+
+    /// Flushes the buffered items whenever a future created by `factory`
+    /// resolves.
+    ///
+    /// The next future is created by the following poll of the stream.
+    /// This allows arbitrary flush conditions such as a notification from
+    /// another task or a reload of the configuration. Like the flush handle,
+    /// the trigger is served once the inner stream went idle, and a future
+    /// that resolves while nothing is buffered has no effect. An error of the
+    /// future is returned by the stream, after the buffered items, and a new
+    /// future is created on the next poll.
+    pub fn flush_when<F, U>(mut self, mut factory: F) -> Chunks<S, B>
+    where
+        F: FnMut() -> U + Send + 'static,
+        U: IntoFuture<Item = ()>,
+        U::Future: Send + 'static,
+        U::Error: Into<BoxError> + 'static,
+    {
+        let factory =
+            move || -> FlushFuture { Box::new(factory().into_future().map_err(Into::into)) };
+        self.flush_when = Some((Box::new(factory), None));
+        self
+    }
+
+    /// Returns whether a future of `flush_when` resolved while items are
+    /// buffered.
+    fn poll_when(&mut self) -> Result<bool, BoxError> {
+        let (factory, pending) = match self.flush_when {
+            Some((ref mut factory, ref mut pending)) => (factory, pending),
+            None => return Ok(false),
+        };
+        let polled = pending.get_or_insert_with(|| factory()).poll();
+        match polled {
+            Ok(Async::NotReady) => Ok(false),
+            // The next future is created by the next poll, which is only
+            // needed to wake up the task once items are buffered.
+            Ok(Async::Ready(())) => {
+                *pending = None;
+                Ok(!self.items.is_empty())
+            }
+            Err(e) => {
+                *pending = None;
+                Err(e)
+            }
+        }
+    }
+
+    fn poll_shutdown(&mut self) -> bool {
+        match self.shutdown.poll() {
+            Ok(Async::Ready(None)) | Ok(Async::NotReady) => false,
+            Ok(Async::Ready(Some(()))) => {
+                self.shutdown = None;
+                true
+            }
+            Err(_) => {
+                self.shutdown = None;
+                !self.ignore_shutdown_cancel
+            }
+        }
+    }
+
+    fn arm(&mut self, item: &S::Item) {
+        if self.mode == FlushMode::CapacityOnly {
+            return;
+        }
+        let now = clock::now();
+        let duration = match (self.catchup, &self.item_timeout) {
+            (Some((catchup, true)), _) => catchup,
+            (_, Some(_)) => self.duration,
+            (_, None) => self.next_timeout(),
+        };
+        let deadline = match (&self.item_timeout, &mut self.jitter, self.tick) {
+            _ if self.first_deadline.is_some() => self.first_deadline.take().unwrap(),
+            (Some(f), _, _) => now + f(item),
+            (None, _, Some(tick)) if self.mode == FlushMode::Interval => self.align(tick, now),
+            (None, Some(jitter), _) => now + duration + jitter.offset(),
+            (None, None, _) => now + duration,
+        };
+
+        match self.clock {
+            Some(ref mut clock) if !self.items.is_empty() => {
+                if deadline < clock.deadline() {
+                    clock.reset(deadline);
+                }
+            }
+            _ => self.clock = Some(Delay::new(deadline)),
+        }
+    }
+
+    /// Returns whether a tick of `with_capacity_and_interval` is due, moving
+    /// on to the next one if it is.
+    fn poll_heartbeat(&mut self) -> Result<bool, timer::Error> {
+        let (interval, tick) = match self.heartbeat {
+            Some((interval, Some(ref mut tick))) => (interval, tick),
+            _ => return Ok(false),
+        };
+        if tick.poll()?.is_not_ready() {
+            return Ok(false);
+        }
+        let next = tick.deadline() + interval;
+        tick.reset(next);
+        Ok(true)
+    }
+
+    /// Moves the deadline of the batch closer for a high priority item, see
+    /// `high_priority_timeout`.
+    fn hurry(&mut self, item: &S::Item) {
+        let (timeout, classify) = match (self.priority_timeout, &self.priority) {
+            (Some(timeout), Some((classify, _))) => (timeout, classify),
+            _ => return,
+        };
+        if self.mode == FlushMode::CapacityOnly || classify(item) != Priority::High {
+            return;
+        }
+        let deadline = clock::now() + timeout;
+        match self.clock {
+            Some(ref mut clock) if deadline < clock.deadline() => clock.reset(deadline),
+            Some(_) => {}
+            None => self.clock = Some(Delay::new(deadline)),
+        }
+    }
+
+    /// Returns the timeout for the next batch, moving on in the cycle of
+    /// `timeouts`.
+    fn next_timeout(&mut self) -> Duration {
+        match self.timeout_cycle {
+            Some((ref timeouts, ref mut next)) => {
+                let timeout = timeouts[*next];
+                *next = (*next + 1) % timeouts.len();
+                timeout
+            }
+            None => self.duration,
+        }
+    }
+
+    /// Returns the deadline for the batch that starts at `now`, given the
+    /// next tick of the interval schedule.
+    fn align(&self, tick: Instant, now: Instant) -> Instant {
+        let period = self.duration.as_nanos();
+        if tick > now || period == 0 || self.missed_tick == MissedTickBehavior::Burst {
+            return tick;
+        }
+        let missed = (now - tick).as_nanos() / period + 1;
+        tick + Duration::from_nanos((missed * period).min(u128::from(u64::MAX)) as u64)
+    }
+
+    /// Sets what happens to missed ticks in `FlushMode::Interval`.
+    ///
+    /// By default missed ticks are made up for with `MissedTickBehavior::Burst`.
+    #[cfg(feature = "timer")]
+    pub fn missed_tick_behavior(mut self, behavior: MissedTickBehavior) -> Chunks<S, B> {
+        self.missed_tick = behavior;
+        self
+    }
+
+    /// Sets what happens to the timer when a batch is flushed early because
+    /// it reached its capacity.
+    ///
+    /// By default the timer is reset with `TimerPolicy::Reset`, which times
+    /// every batch from its first item. `TimerPolicy::Keep` keeps a fixed
+    /// schedule instead: the timer runs on, and the items that arrive before
+    /// it fires are flushed together when it does.
+    #[cfg(feature = "timer")]
+    pub fn timer_on_capacity_flush(mut self, policy: TimerPolicy) -> Chunks<S, B> {
+        self.timer_policy = policy;
+        self
+    }
+
+    /// Names this combinator, which tells its `Debug` output and its errors
+    /// apart from other instances in the same process. The label is part of
+    /// the `Display` output of every error it returns.
+    pub fn label(mut self, label: &'static str) -> Chunks<S, B> {
+        self.label = Some(label);
+        self
+    }
+
+    /// Changes which triggers flush a batch on a live stream.
+    ///
+    /// Switching to `FlushMode::CapacityOnly` disarms the timer of the
+    /// current batch. Switching to a mode with a timeout arms the timer for
+    /// the current batch if it is not armed yet, starting from now.
+    ///
+    /// # Panics
+    ///
+    /// Without the `timer` feature, this method panics for any mode but
+    /// `FlushMode::CapacityOnly`.
+    #[track_caller]
+    pub fn set_mode(&mut self, mode: FlushMode) {
+        assert!(
+            cfg!(feature = "timer") || mode == FlushMode::CapacityOnly,
+            "FlushMode::{:?} requires the `timer` feature",
+            mode
+        );
+        self.mode = mode;
+        if mode != FlushMode::Interval {
+            self.tick = None;
+        }
+        if mode == FlushMode::CapacityOnly {
+            self.clock = None;
+        } else if self.clock.is_none() && !self.items.is_empty() {
+            self.clock = Some(Delay::new(clock::now() + self.duration));
+        }
+    }
+
+    /// Finishes the stream without waiting for the inner stream to end.
+    ///
+    /// The next poll emits the buffered items as the final batch, if there
+    /// are any, and the stream ends right after. The inner stream is not
+    /// polled anymore, which allows to stop batching an infinite stream.
+    pub fn complete(&mut self) {
+        self.completing = true;
+    }
+
+    /// Replaces the timeout and flushes the buffered items right away, so
+    /// that the new timeout applies from the next batch on.
+    ///
+    /// The timer of the current batch is disarmed along with the flush, so it
+    /// cannot fire with the old timeout after the change. This also replaces
+    /// the timeouts of `timeouts` and restarts the ticks of
+    /// `FlushMode::Interval`. The returned batch bypasses `validate`.
+    #[cfg(feature = "timer")]
+    pub fn reconfigure_and_flush(&mut self, duration: Duration) -> Option<B> {
+        self.duration = duration;
+        self.timeout_cycle = None;
+        self.tick = None;
+        self.clock = None;
+        if self.items.is_empty() {
+            return None;
+        }
+        match self.flush(FlushReason::Reconfigure) {
+            Ok(Async::Ready(Some(batch))) => Some(self.finish(batch)),
+            _ => unreachable!("flushing a non-empty batch always emits it"),
+        }
+    }
+
+    /// Checks that the configured options are consistent with each other,
+    /// and returns this combinator if they are.
+    ///
+    /// This rejects a `soft_flush_at` or `absorb_small_tail` above the
+    /// capacity, which would never take effect, and a `hard_cap` below the
+    /// capacity, which would never let a batch fill up. With a
+    /// `capacity_schedule`, the capacity of the current batch is checked.
+    /// Any `hard_cap` is fine for the modes where the capacity does not
+    /// bound a batch, i.e. `weighted` and `FlushMode::Interval`.
+    pub fn build(self) -> Result<Chunks<S, B>, BuildError> {
+        let bounded = self.weight.is_none() && self.mode != FlushMode::Interval;
+        check_bounds(
+            self.capacity,
+            &[
+                ("soft_flush_at", self.soft_flush_at),
+                ("absorb_small_tail", self.absorb_small_tail),
+            ],
+            ("hard_cap", self.hard_cap.filter(|_| bounded)),
+        )?;
+        Ok(self)
+    }
+
+    /// Sets how the buffer of each batch is allocated.
+    ///
+    /// By default room for the whole capacity is allocated up front, which
+    /// can be wasteful if the capacity is high but batches are usually small.
+    pub fn growth_policy(self, policy: GrowthPolicy) -> Chunks<S, B> {
+        self.try_growth_policy(policy)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like `growth_policy`, but fails instead of panicking if the growth
+    /// step is zero.
+    pub fn try_growth_policy(mut self, policy: GrowthPolicy) -> Result<Chunks<S, B>, BuildError> {
+        if let GrowthPolicy::FixedStep(0) = policy {
+            return Err(BuildError::InvalidGrowthStep(0));
+        }
+
+        self.growth_policy = policy;
+        if self.items.is_empty() {
+            self.items = self.buffer();
+        }
+        Ok(self)
+    }
+
+    /// Reserves room for at least `additional` more items in the buffer of
+    /// the current batch.
+    pub fn reserve(&mut self, additional: usize) {
+        self.items.reserve(additional);
+    }
+
+    fn buffer(&self) -> B {
+        match self.growth_policy {
+            GrowthPolicy::Preallocate => B::with_capacity(self.capacity.min(MAX_PREALLOCATE)),
+            GrowthPolicy::Doubling => B::with_capacity(0),
+            GrowthPolicy::FixedStep(step) => B::with_capacity(step.min(self.capacity)),
+        }
+    }
+
+    fn push(&mut self, item: S::Item) {
+        if let GrowthPolicy::FixedStep(step) = self.growth_policy {
+            if self.items.len() == self.items.capacity() {
+                self.items.reserve_exact(step);
+            }
+        }
+        self.items.push(item);
+    }
+
+    fn take(&mut self) -> B {
+        self.taken = Usage {
+            memory: self.memory.as_ref().map_or(0, |memory| memory.used),
+            weight: self.weight.as_ref().map_or(0, |weight| weight.used),
+        };
+        if let Some(ref mut filter) = self.filter {
+            filter.reset();
+        }
+        if let Some(ref mut memory) = self.memory {
+            memory.used = 0;
+        }
+        if let Some(ref mut weight) = self.weight {
+            weight.used = 0;
+        }
+        if let Some(ref mut grace) = self.grace {
+            grace.extended = false;
+        }
+        if let Some((ref mut schedule, ref mut batch)) = self.schedule {
+            *batch += 1;
+            self.capacity = schedule(*batch);
+            assert!(
+                self.capacity > 0,
+                "capacity schedule returned 0 for batch {}",
+                batch
+            );
+        }
+        let buffer = self.buffer();
+        mem::replace(&mut self.items, buffer)
+    }
+
+    /// Acquires a reference to the underlying stream that this combinator is
+    /// pulling from.
+    pub fn get_ref(&self) -> &S {
+        self.stream.get_ref()
+    }
+
+    /// Acquires a mutable reference to the underlying stream that this
+    /// combinator is pulling from.
+    ///
+    /// Note that care must be taken to avoid tampering with the state of the
+    /// stream which may otherwise confuse this combinator.
+    pub fn get_mut(&mut self) -> &mut S {
+        self.stream.get_mut()
+    }
+
+    /// Consumes this combinator, returning the underlying stream.
+    ///
+    /// Note that this may discard intermediate state of this combinator, so
+    /// care should be taken to avoid losing resources when this is called.
+    pub fn into_inner(self) -> S {
+        self.stream.into_inner()
+    }
+
+    /// Consumes this combinator, returning the underlying stream and the
+    /// items that were buffered but not emitted yet.
+    ///
+    /// Unlike `into_inner`, no items are lost, so this is the way to cancel a
+    /// batcher mid-batch. The items are returned in the order they were
+    /// pulled from the stream, including those of a batch that is still
+    /// being validated.
+    pub fn into_remaining(mut self) -> (S, B) {
+        let mut remaining = self
+            .validating
+            .take()
+            .map_or_else(|| B::with_capacity(0), |(batch, _, _)| batch);
+        if let Some((held, _)) = self.held.take() {
+            remaining.append(held);
+        }
+        remaining.append(self.items.take());
+        if let Some((item, _, _)) = self.carried.take() {
+            remaining.push(item);
+        }
+        (self.stream.into_inner(), remaining)
+    }
+
+    /// Boxes this combinator, erasing the type of the underlying stream.
+    ///
+    /// This makes it easier to store a batched stream in a struct field or to
+    /// return it from a function. Streams are polled through `&mut self`, so
+    /// the box needs no pinning and the inner stream does not need to be
+    /// `Unpin` or similar.
+    pub fn boxed(self) -> Box<dyn Stream<Item = B, Error = Error<S::Error>> + Send>
+    where
+        S: Send + 'static,
+        S::Item: Send,
+        S::Error: Send,
+        B: Send + 'static,
+    {
+        Box::new(self)
+    }
+
+    /// Boxes this combinator like `boxed`, but without requiring the stream
+    /// to be `Send`.
+    ///
+    /// This is meant for streams that hold an `Rc` or similar and are driven
+    /// on a single-threaded runtime.
+    pub fn boxed_local(self) -> Box<dyn Stream<Item = B, Error = Error<S::Error>>>
+    where
+        S: 'static,
+        B: 'static,
+    {
+        Box::new(self)
+    }
+
+    /// Converts the errors of this combinator into another error type.
+    ///
+    /// This is the same as `Stream::map_err`, but keeps the batch type spelled
+    /// out, which helps when integrating with a crate-wide error type.
+    ///
+    /// ```
+    /// use std::io;
+    ///
+    /// use futures::{stream, Stream};
+    /// use tokio::runtime::current_thread;
+    /// use tokio_batch::{Chunks, Error};
+    ///
+    /// #[derive(Debug)]
+    /// enum AppError {
+    ///     Batching(Error<io::Error>),
+    /// }
+    ///
+    /// let stream = stream::iter_result(vec![
+    ///     Ok(1),
+    ///     Err(io::Error::new(io::ErrorKind::Other, "boom")),
+    /// ]);
+    /// let chunk_stream = Chunks::with_capacity(stream, 5).map_err(AppError::Batching);
+    ///
+    /// match current_thread::block_on_all(chunk_stream.collect()) {
+    ///     Err(AppError::Batching(_)) => {}
+    ///     Ok(v) => panic!("unexpected batches {:?}", v),
+    /// }
+    /// ```
+    pub fn map_err<E, F>(self, f: F) -> MapErr<Chunks<S, B>, F>
+    where
+        F: FnMut(Error<S::Error>) -> E,
+    {
+        Stream::map_err(self, f)
+    }
+
+    /// Replaces the underlying stream, returning the old one.
+    ///
+    /// The items buffered so far and the timer of the current batch are kept,
+    /// so the next batch may contain items from both streams. This is useful
+    /// to reconnect to a source without losing a partial batch. If the old
+    /// stream had completed, the combinator starts pulling again.
+    pub fn replace_stream(&mut self, s: S) -> S {
+        let unfused = match self.stream {
+            Inner::Unfused { .. } => Inner::unfused(s),
+            Inner::Fused(_) => Inner::fused(s),
+        };
+        self.done = false;
+        mem::replace(&mut self.stream, unfused).into_inner()
+    }
+
+    /// Starts a fresh run on `new_stream`, keeping the configuration.
+    ///
+    /// Unlike `replace_stream`, everything left over from the previous run
+    /// is dropped: the buffered, held and carried items, the timer and any
+    /// deferred error. The new stream is always fused. The counters keep
+    /// adding up across runs.
+    pub fn reset(&mut self, new_stream: S) {
+        self.stream = Inner::fused(new_stream);
+        self.items = self.buffer();
+        if let Some(ref mut filter) = self.filter {
+            filter.reset();
+        }
+        if let Some(ref mut memory) = self.memory {
+            memory.used = 0;
+        }
+        if let Some(ref mut weight) = self.weight {
+            weight.used = 0;
+        }
+        if let Some(ref mut grace) = self.grace {
+            grace.extended = false;
+        }
+        self.held = None;
+        self.carried = None;
+        self.err = None;
+        self.validating = None;
+        self.clock = None;
+        self.floor = None;
+        self.tick = None;
+        self.first_deadline = None;
+        self.started_at = None;
+        self.flushed_at = None;
+        self.last_reason = None;
+        self.unpadded_len = 0;
+        self.ready_streak = 0;
+        self.inner_failures = 0;
+        self.completing = false;
+        self.done = false;
+        if let Some((_, ref mut index)) = self.timeout_cycle {
+            *index = 0;
+        }
+        if let Some((_, ref mut active)) = self.catchup {
+            *active = false;
+        }
+        if let Some((_, ref mut pending)) = self.flush_when {
+            *pending = None;
+        }
+        if let Some((_, ref mut tick)) = self.heartbeat {
+            *tick = None;
+        }
+    }
+
+    /// Returns a copy of the items that are currently buffered.
+    ///
+    /// The batch that is being accumulated is not touched, so the returned
+    /// vector can be sent elsewhere while the stream keeps running.
+    pub fn snapshot(&self) -> B
+    where
+        B: Clone,
+    {
+        self.items.clone()
+    }
+
+    /// Returns an iterator over the batches that can be emitted right away.
+    ///
+    /// This lets synchronous code drain the combinator whenever it is
+    /// convenient. The iterator stops as soon as the next batch would have to
+    /// wait, leaving the items of the partial batch buffered. Like `poll`, it
+    /// must be called from within a task.
+    pub fn poll_ready_batches(&mut self) -> ReadyBatches<'_, S, B> {
+        ReadyBatches::new(self)
+    }
+
+    /// Returns the time left until the current batch is flushed by the
+    /// timer, or `None` if no batch is being accumulated.
+    pub fn time_until_flush(&self) -> Option<Duration> {
+        self.clock.as_ref().map(|clock| {
+            let now = clock::now();
+            let deadline = clock.deadline();
+            if deadline > now {
+                deadline - now
+            } else {
+                Duration::new(0, 0)
+            }
+        })
+    }
+
+    /// Returns whether the timer of the current batch is armed.
+    pub fn is_timer_armed(&self) -> bool {
+        self.clock.is_some()
+    }
+
+    /// Returns whether the next batch is expected to be flushed by the timer.
+    ///
+    /// This is the case when items are buffered, the buffer is below capacity
+    /// and the timer is armed. Together with `time_until_flush` this allows
+    /// consumers to prepare for a small, timeout-driven batch.
+    pub fn next_flush_will_be_timeout(&self) -> bool {
+        !self.items.is_empty() && self.items.len() < self.capacity && self.clock.is_some()
+    }
+
+    /// Returns the error that is returned by the next `poll`, if any.
+    ///
+    /// An error of the inner stream or the timer that occurs while items are
+    /// buffered is held back until the buffered items were emitted. The batch
+    /// with those items may still be waiting to be returned, as the error is
+    /// only ever returned after it.
+    pub fn pending_error(&self) -> Option<&Error<S::Error>> {
+        self.err.as_ref()
+    }
+
+    /// Takes the error that would have been returned by the next `poll`.
+    ///
+    /// A later `poll` then continues with the inner stream as if nothing
+    /// happened, which allows to ignore errors out of band. To give up on the
+    /// partial batch instead, use `into_remaining`.
+    pub fn take_pending_error(&mut self) -> Option<Error<S::Error>> {
+        self.err.take()
+    }
+
+    /// Returns a handle to flush the current batch from the outside.
+    ///
+    /// All handles returned by this method request the flush of the same
+    /// combinator. Like the other triggers, a request is served once the
+    /// next item was pulled or the inner stream went idle.
+    pub fn flush_handle(&mut self) -> FlushHandle {
+        self.flush_handle
+            .get_or_insert_with(FlushHandle::new)
+            .clone()
+    }
+
+    /// Returns why the last batch was flushed, or `None` if none was flushed
+    /// yet.
+    ///
+    /// Apart from the timer, the triggers are checked in a fixed order after
+    /// every item: the hard cap, the capacity, the weight, the memory of
+    /// `max_memory`, the boundary of `flush_on` and the `FlushHandle`. The
+    /// first one that holds is the reason.
+    pub fn last_flush_reason(&self) -> Option<FlushReason> {
+        self.last_reason
+    }
+
+    /// Returns an estimate of the largest buffer a batch may need, in bytes.
+    ///
+    /// This is the capacity times the size of an item and does not include
+    /// memory that the items own on the heap. For a `Chunks::weighted`
+    /// combinator it is `max_weight` instead, in the units of the weight
+    /// function, which makes it a byte count if the weights are item sizes.
+    pub fn max_buffer_bytes(&self) -> usize {
+        match self.weight {
+            Some(ref weight) => weight.max,
+            None => self.capacity.saturating_mul(mem::size_of::<S::Item>()),
+        }
+    }
+
+    /// Returns the activity counters accumulated so far.
+    pub fn counters(&self) -> Counters {
+        self.counters
+    }
+
+    /// Returns the activity counters accumulated since the last call and
+    /// resets them, which suits delta-based metrics systems.
+    pub fn take_counters(&mut self) -> Counters {
+        mem::take(&mut self.counters)
+    }
+
+    /// Returns the number of items pulled from the inner stream.
+    pub fn items_seen(&self) -> u64 {
+        self.counters.items
+    }
+
+    /// Returns the number of batches emitted.
+    pub fn batches_emitted(&self) -> u64 {
+        self.counters.batches
+    }
+
+    /// Returns the time since this combinator was created.
+    pub fn uptime(&self) -> Duration {
+        clock::now().saturating_duration_since(self.created_at)
+    }
+
+    /// Returns the uptime divided by the number of batches emitted, or `None`
+    /// if none was emitted yet.
+    ///
+    /// As `take_counters` starts the count of batches over, this is only
+    /// meaningful if the counters were never taken.
+    pub fn avg_batch_interval(&self) -> Option<Duration> {
+        match self.counters.batches {
+            0 => None,
+            batches => {
+                let nanos = self.uptime().as_nanos() / u128::from(batches);
+                Some(Duration::from_nanos(nanos.min(u128::from(u64::MAX)) as u64))
+            }
+        }
+    }
+
+    /// Returns the distribution of the gaps between the arrivals of
+    /// consecutive items.
+    ///
+    /// This helps to choose a timeout and capacity that suit the source: a
+    /// timeout well above `p99` rarely flushes a batch while items are still
+    /// flowing. Every pulled item is counted, including the ones dropped by
+    /// a filter.
+    #[cfg(feature = "metrics")]
+    pub fn arrival_gap_stats(&self) -> GapStats {
+        self.gaps.stats()
+    }
+
+    /// Adds an accepted item to the batch, returning why the batch has to be
+    /// flushed if it is complete now.
+    fn append(
+        &mut self,
+        item: S::Item,
+        weight: usize,
+        boundary: Option<Sentinel>,
+    ) -> Option<FlushReason> {
+        if let Some(ref inspect) = self.inspect {
+            inspect(&item);
+        }
+        // An item that fills up the batch is flushed right away, so there is
+        // no point in arming the timer for it. This keeps a capacity of 1 from
+        // creating a `Delay` per item.
+        let fills = self.mode != FlushMode::Interval && self.items.len() + 1 >= self.capacity;
+        let kept =
+            self.timer_policy == TimerPolicy::Keep && self.items.is_empty() && self.clock.is_some();
+        if !fills && !kept && (self.items.is_empty() || self.item_timeout.is_some()) {
+            self.arm(&item);
+        }
+        if !fills {
+            self.hurry(&item);
+        }
+        if let Some(ref mut memory) = self.memory {
+            memory.used += (memory.size_of)(&item);
+        }
+        if self.items.is_empty() {
+            self.started_at = Some(clock::now());
+        }
+        self.push(item);
+        if let Some((every, ref on_progress)) = self.progress {
+            if self.items.len() % every == 0 {
+                on_progress(self.items.len());
+            }
+        }
+        if let Some(ref mut w) = self.weight {
+            w.used = w.used.saturating_add(weight);
+        }
+        Trigger::ORDER
+            .iter()
+            .find(|&&trigger| self.holds(trigger, boundary.is_some()))
+            .map(|&trigger| match trigger {
+                Trigger::HardCap => FlushReason::HardCap,
+                Trigger::Count => FlushReason::Capacity,
+                Trigger::Weight => FlushReason::Weight,
+                Trigger::Memory => FlushReason::Memory,
+                Trigger::Boundary => FlushReason::Boundary,
+                Trigger::Signal => FlushReason::Signal,
+            })
+    }
+
+    /// Returns whether `trigger` holds for the current batch, where
+    /// `boundary` tells whether its last item matched `flush_on`.
+    fn holds(&self, trigger: Trigger, boundary: bool) -> bool {
+        match trigger {
+            Trigger::HardCap => self.hard_cap.is_some_and(|cap| self.items.len() >= cap),
+            Trigger::Count => self.mode != FlushMode::Interval && self.items.len() >= self.capacity,
+            Trigger::Weight => {
+                self.mode != FlushMode::Interval
+                    && self.weight.as_ref().is_some_and(|w| w.used >= w.target)
+            }
+            Trigger::Memory => self
+                .memory
+                .as_ref()
+                .is_some_and(|memory| memory.used >= memory.max),
+            Trigger::Boundary => boundary,
+            Trigger::Signal => self
+                .flush_handle
+                .as_ref()
+                .is_some_and(|handle| handle.is_requested()),
+        }
+    }
+
+    /// Returns whether a flush was requested through the `FlushHandle` while
+    /// items are buffered, registering the current task for the next request
+    /// otherwise.
+    fn poll_signal(&mut self) -> bool {
+        let handle = match self.flush_handle {
+            Some(ref handle) => handle,
+            None => return false,
+        };
+        if handle.poll_requested() {
+            if !self.items.is_empty() {
+                return true;
+            }
+            handle.clear();
+        }
+        false
+    }
+
+    fn flush(&mut self, reason: FlushReason) -> Poll<Option<B>, Error<S::Error>> {
+        match reason {
+            FlushReason::Capacity | FlushReason::Weight => {
+                self.counters.capacity_flushes = self.counters.capacity_flushes.saturating_add(1);
+                if let Some((_, ref mut active)) = self.catchup {
+                    *active = false;
+                }
+            }
+            FlushReason::Timeout => {
+                self.counters.timeouts = self.counters.timeouts.saturating_add(1);
+                if let Some((_, ref mut active)) = self.catchup {
+                    *active = true;
+                }
+            }
+            _ => {}
+        }
+        let early = matches!(reason, FlushReason::Capacity | FlushReason::Weight);
+        if !early || self.timer_policy == TimerPolicy::Reset {
+            self.clock = None;
+        }
+        if let Some(ref handle) = self.flush_handle {
+            handle.clear();
+        }
+        self.last_reason = Some(reason);
+        self.floor = None;
+        self.flushed_at = self.started_at.take().map(|at| (at, clock::now()));
+        Ok(Some(self.take()).into())
+    }
+
+    /// Checks whether `min_latency` passed since the last batch was emitted,
+    /// scheduling a wakeup for when it does otherwise.
+    fn poll_min_latency(&mut self) -> Poll<(), timer::Error> {
+        let at = match (self.min_latency, self.flushed_at) {
+            (Some(min_latency), Some((_, emitted_at))) => emitted_at + min_latency,
+            _ => return Ok(Async::Ready(())),
+        };
+        if at <= clock::now() {
+            return Ok(Async::Ready(()));
+        }
+        match self.floor {
+            Some(ref floor) if floor.deadline() == at => {}
+            _ => self.floor = Some(Delay::new(at)),
+        }
+        if let Async::NotReady = self.floor.poll()? {
+            return Ok(Async::NotReady);
+        }
+        Ok(Async::Ready(()))
+    }
+
+    /// Returns when the first item of the last flushed batch arrived and when
+    /// that batch was flushed.
+    pub(crate) fn flush_times(&self) -> Option<(Instant, Instant)> {
+        self.flushed_at
+    }
+}
+
+impl<S> Chunks<RoundRobin<S>>
+where
+    S: Stream,
+{
+    /// Batches the items of several streams together.
+    ///
+    /// The streams are polled in turn so that every one of them gets a fair
+    /// share of each batch. Streams that complete are dropped, and the
+    /// combinator completes once all of them did.
+    #[cfg(feature = "timer")]
+    #[track_caller]
+    pub fn from_streams(streams: Vec<S>, capacity: usize, duration: Duration) -> Self {
+        Chunks::new(RoundRobin::new(streams), capacity, duration)
+    }
+}
+
+/// Creates a batched stream from a closure producing the items.
+///
+/// This wraps `f` in a `futures::stream::poll_fn` and batches its output,
+/// which is handy for quick prototypes and tests.
+///
+/// ```
+/// use std::io;
+/// use std::time::Duration;
+///
+/// use futures::{Async, Stream};
+/// use tokio::runtime::current_thread;
+///
+/// let mut n = 0;
+/// let chunk_stream = tokio_batch::chunks_from_fn(2, Duration::new(10, 0), move || {
+///     n += 1;
+///     if n <= 5 {
+///         Ok::<_, io::Error>(Async::Ready(Some(n)))
+///     } else {
+///         Ok(Async::Ready(None))
+///     }
+/// });
+///
+/// let v = current_thread::block_on_all(chunk_stream.collect()).unwrap();
+/// assert_eq!(v, vec![vec![1, 2], vec![3, 4], vec![5]]);
+/// ```
+#[cfg(feature = "timer")]
+#[track_caller]
+pub fn chunks_from_fn<T, E, F>(capacity: usize, duration: Duration, f: F) -> Chunks<PollFn<F>>
+where
+    F: FnMut() -> Poll<Option<T>, E>,
+{
+    Chunks::new(stream::poll_fn(f), capacity, duration)
+}
+
+impl<S, B> Chunks<S, B>
+where
+    S: Stream,
+    B: Batch<S::Item>,
+{
+    fn state(&self) -> State {
+        if self.err.is_some() {
+            State::PendingError
+        } else if self.done {
+            State::Done
+        } else if !self.items.is_empty() || self.carried.is_some() {
+            State::Accumulating
+        } else {
+            State::Idle
+        }
+    }
+
+    fn poll_batch(&mut self) -> Poll<Option<B>, Error<S::Error>> {
+        match self.state() {
+            State::PendingError => self.err.take().map_or_else(|| self.poll_items(), Err),
+            State::Done if self.items.is_empty() => Ok(Async::Ready(None)),
+            // Items may have been buffered again by `validate` after the
+            // inner stream ended.
+            State::Done => self.flush(FlushReason::End),
+            State::Accumulating => {
+                // An item that was held back by the weight budget of the
+                // previous batch goes first into the fresh one.
+                if let Some((item, weight, boundary)) = self.carried.take() {
+                    if let Some(ref mut filter) = self.filter {
+                        filter.accept(&item);
+                    }
+                    if let Some(reason) = self.append(item, weight, boundary) {
+                        return self.flush(reason);
+                    }
+                }
+                self.poll_items()
+            }
+            State::Idle => self.poll_items(),
+        }
+    }
+
+    /// Pulls items from the inner stream until a batch is complete.
+    fn poll_items(&mut self) -> Poll<Option<B>, Error<S::Error>> {
+        let pressure = self
+            .pressure
+            .as_ref()
+            .is_some_and(|flag| flag.swap(false, Ordering::SeqCst));
+        if pressure && !self.items.is_empty() {
+            return self.flush(FlushReason::Pressure);
+        }
+
+        // Once the shutdown signal fired or `complete` was called, emit what we
+        // have buffered as the final batch without touching the inner stream
+        // anymore.
+        if self.completing || self.poll_shutdown() {
+            self.done = true;
+            return if !self.items.is_empty() {
+                self.flush(FlushReason::Shutdown)
+            } else {
+                Ok(Async::Ready(None))
+            };
+        }
+
+        loop {
+            let paused = self.pause.as_ref().is_some_and(|pause| pause.poll_paused());
+            if let Some((interval, ref mut tick)) = self.heartbeat {
+                tick.get_or_insert_with(|| Delay::new(clock::now() + interval));
+            }
+            // A stream that never runs dry would otherwise keep the clock
+            // from ever being polled, e.g. if its items are filtered out, so
+            // an overdue batch stops pulling until the timer flushed it.
+            // Without the `timer` feature the timer never fires, so nothing
+            // is ever overdue.
+            let overdue = cfg!(feature = "timer")
+                && self
+                    .clock
+                    .iter()
+                    .chain(self.heartbeat.as_ref().and_then(|(_, tick)| tick.as_ref()))
+                    .any(|delay| delay.deadline() <= clock::now());
+            let polled = match self.rate_limit {
+                _ if paused || overdue => Ok(Async::NotReady),
+                Some(ref mut rate_limit) => match rate_limit.poll_token() {
+                    Ok(Async::Ready(())) => self.stream.poll(),
+                    Ok(Async::NotReady) => Ok(Async::NotReady),
+                    Err(e) => {
+                        if self.items.is_empty() {
+                            return Err(self.error(Kind::Timer(e)));
+                        } else {
+                            self.err = Some(self.error(Kind::Timer(e)));
+                            return self.flush(FlushReason::Error);
+                        }
+                    }
+                },
+                None => self.stream.poll(),
+            };
+
+            match polled {
+                // An overdue batch goes straight to the clock below.
+                Ok(Async::NotReady) if overdue => {}
+                // Once the stream runs dry, a requested flush is served and a
+                // batch that reached the soft threshold is flushed. Otherwise
+                // the clock is polled below, which registers this task with
+                // the timer, so a stream that stalls forever still gets
+                // flushed.
+                Ok(Async::NotReady) => {
+                    if self.poll_signal() {
+                        return self.flush(FlushReason::Signal);
+                    }
+                    match self.poll_when() {
+                        Ok(true) => return self.flush(FlushReason::Custom),
+                        Ok(false) => {}
+                        Err(e) if self.items.is_empty() => return Err(self.error(Kind::Trigger(e))),
+                        Err(e) => {
+                            self.err = Some(self.error(Kind::Trigger(e)));
+                            return self.flush(FlushReason::Error);
+                        }
+                    }
+                    let len = self.items.len();
+                    if len > 0 && self.soft_flush_at.is_some_and(|n| len >= n) {
+                        match self.poll_min_latency() {
+                            Ok(Async::Ready(())) => return self.flush(FlushReason::Idle),
+                            Ok(Async::NotReady) => {}
+                            Err(e) => {
+                                self.err = Some(self.error(Kind::Timer(e)));
+                                return self.flush(FlushReason::Error);
+                            }
+                        }
+                    }
+                }
+
+                // Push the item into the buffer and check whether it is full.
+                // If so, replace our buffer with a new and empty one and return
+                // the full one.
+                Ok(Async::Ready(Some(item))) => {
+                    self.counters.items = self.counters.items.saturating_add(1);
+                    #[cfg(feature = "metrics")]
+                    self.gaps.record(clock::now());
+                    self.inner_failures = 0;
+                    if let Some(ref mut rate_limit) = self.rate_limit {
+                        rate_limit.spend();
+                    }
+                    if let Some(ref mut filter) = self.filter {
+                        if !filter.accept(&item) {
+                            continue;
+                        }
+                    }
+                    let boundary = self.is_boundary(&item);
+                    if boundary == Some(Sentinel::Drop) {
+                        if self.items.is_empty() {
+                            continue;
+                        }
+                        return self.flush(FlushReason::Boundary);
+                    }
+                    let weight = match self.weight {
+                        Some(ref weight) => match (weight.weigh)(&item) {
+                            Ok(n) => n,
+                            Err(e) => {
+                                if self.items.is_empty() {
+                                    return Err(self.error(Kind::Weight(e)));
+                                } else {
+                                    self.err = Some(self.error(Kind::Weight(e)));
+                                    return self.flush(FlushReason::Error);
+                                }
+                            }
+                        },
+                        None => 0,
+                    };
+                    // An item that does not fit into the weight budget of the
+                    // current batch starts the next one.
+                    let overweight = self
+                        .weight
+                        .as_ref()
+                        .is_some_and(|w| w.used.saturating_add(weight) > w.max);
+                    if overweight && !self.items.is_empty() {
+                        self.carried = Some((item, weight, boundary));
+                        return self.flush(FlushReason::Weight);
+                    }
+                    // The same goes for the first item of a new run.
+                    let new_run = self.runs.as_mut().is_some_and(|runs| runs(&item));
+                    if new_run && !self.items.is_empty() {
+                        self.carried = Some((item, weight, boundary));
+                        return self.flush(FlushReason::KeyChange);
+                    }
+                    match self.append(item, weight, boundary) {
+                        Some(reason) => return self.flush(reason),
+                        None => continue,
+                    }
+                }
+
+                // Since the underlying stream ran out of values, return what we
+                // have buffered, if we have anything. The stream is not polled
+                // again after that, and the ticks of `FlushMode::Interval` end
+                // with it, so no empty final batch is emitted.
+                Ok(Async::Ready(None)) => {
+                    self.done = true;
+                    return if !self.items.is_empty() {
+                        self.flush(FlushReason::End)
+                    } else {
+                        Ok(Async::Ready(None))
+                    };
+                }
+
+                // If we've got buffered items be sure to return them first,
+                // we'll defer our error for later.
+                Err(e) => {
+                    if self.retry_inner.is_some_and(|n| self.inner_failures < n) {
+                        self.inner_failures += 1;
+                        if self.items.is_empty() || self.hold_on_error {
+                            continue;
+                        }
+                        return self.flush(FlushReason::Retry);
+                    }
+                    if self.items.is_empty() {
+                        return Err(self.error(Kind::Inner(e)));
+                    } else {
+                        self.err = Some(self.error(Kind::Inner(e)));
+                        return self.flush(FlushReason::Error);
+                    }
+                }
+            }
+
+            match self.clock.poll() {
+                // A timer that was kept across a capacity flush fired before
+                // any items arrived for the next batch.
+                Ok(Async::Ready(Some(()))) if self.items.is_empty() => {
+                    self.clock = None;
+                    continue;
+                }
+                Ok(Async::Ready(Some(()))) => {
+                    if let Some(ref mut grace) = self.grace {
+                        let fill = self.items.len() as f64 / self.capacity as f64;
+                        if !grace.extended && fill >= grace.threshold {
+                            grace.extended = true;
+                            self.clock = Some(Delay::new(clock::now() + grace.extension));
+                            continue;
+                        }
+                    }
+                    if self.mode == FlushMode::Interval {
+                        self.tick = self
+                            .clock
+                            .as_ref()
+                            .map(|clock| clock.deadline() + self.duration);
+                    }
+                    return self.flush(FlushReason::Timeout);
+                }
+                // This should never happen, but rather than losing track of
+                // the buffered items, arm a fresh timer for them.
+                Ok(Async::Ready(None)) if self.mode == FlushMode::CapacityOnly => {}
+                Ok(Async::Ready(None)) => {
+                    debug_assert!(self.items.is_empty(), "no clock but there are items");
+                    if !self.items.is_empty() {
+                        self.clock = Some(Delay::new(clock::now() + self.duration));
+                        continue;
+                    }
+                }
+                Ok(Async::NotReady) => {}
+                Err(e) => {
+                    if self.items.is_empty() {
+                        return Err(self.error(Kind::Timer(e)));
+                    } else {
+                        self.err = Some(self.error(Kind::Timer(e)));
+                        return self.flush(FlushReason::Error);
+                    }
+                }
+            }
+
+            match self.poll_heartbeat() {
+                Ok(true) => return self.flush(FlushReason::Heartbeat),
+                Ok(false) => {}
+                Err(e) => {
+                    if self.items.is_empty() {
+                        return Err(self.error(Kind::Timer(e)));
+                    } else {
+                        self.err = Some(self.error(Kind::Timer(e)));
+                        return self.flush(FlushReason::Error);
+                    }
+                }
+            }
+
+            return Ok(Async::NotReady);
+        }
+    }
+}
+
+impl<S, B> Stream for Chunks<S, B>
+where
+    S: Stream,
+    B: Batch<S::Item>,
+{
+    type Item = B;
+    type Error = Error<S::Error>;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some((batch, mut validation, usage)) = self.validating.take() {
+                match validation.poll() {
+                    Ok(Async::Ready(true)) => return Ok(Async::Ready(Some(self.finish(batch)))),
+                    Ok(Async::Ready(false)) => {
+                        if let Some((_, OnReject::Rebuffer)) = self.validate {
+                            self.rebuffer(batch, usage);
+                        }
+                        continue;
+                    }
+                    Ok(Async::NotReady) => {
+                        self.validating = Some((batch, validation, usage));
+                        return Ok(Async::NotReady);
+                    }
+                    Err(e) => return Err(self.error(Kind::Validation(e))),
+                }
+            }
+
+            if let Some(budget) = self.yield_after {
+                if self.ready_streak >= budget {
+                    self.ready_streak = 0;
+                    task::current().notify();
+                    return Ok(Async::NotReady);
+                }
+            }
+
+            let batch = match self.poll_absorbing() {
+                Ok(Async::Ready(Some(batch))) => batch,
+                res => {
+                    if let Ok(Async::NotReady) = res {
+                        self.ready_streak = 0;
+                    }
+                    return res;
+                }
+            };
+            self.ready_streak += 1;
+            match self.validate {
+                Some((ref mut validate, _)) => {
+                    let validation = validate(&batch);
+                    self.validating = Some((batch, validation, self.taken));
+                }
+                None => return Ok(Async::Ready(Some(self.finish(batch)))),
+            }
+        }
+    }
+}
+
+impl<S, B> AsRef<S> for Chunks<S, B>
+where
+    S: Stream,
+    B: Batch<S::Item>,
+{
+    fn as_ref(&self) -> &S {
+        self.get_ref()
+    }
+}
+
+impl<S, B> AsMut<S> for Chunks<S, B>
+where
+    S: Stream,
+    B: Batch<S::Item>,
+{
+    fn as_mut(&mut self) -> &mut S {
+        self.get_mut()
+    }
+}
+
+impl<S, B> Chunks<S, B>
+where
+    S: Stream,
+    B: Batch<S::Item>,
+{
+    /// Creates an error of this combinator, carrying its label.
+    fn error(&self, kind: Kind<S::Error>) -> Error<S::Error> {
+        Error {
+            kind,
+            label: self.label,
+        }
+    }
+
+    /// Counts a batch that is about to be emitted and applies `sort_by_key`,
+    /// `prioritize` and `pad_to_capacity` to it.
+    fn finish(&mut self, mut batch: B) -> B {
+        self.counters.batches = self.counters.batches.saturating_add(1);
+        self.unpadded_len = batch.len();
+        if let Some(ref sort) = self.sort {
+            sort(&mut batch);
+        }
+        if let Some((_, ref partition)) = self.priority {
+            partition(&mut batch);
+        }
+        let heartbeat = self.last_reason == Some(FlushReason::Heartbeat);
+        if let Some(pad) = self.pad.filter(|_| !heartbeat) {
+            let len = self
+                .hard_cap
+                .map_or(self.capacity, |cap| cap.min(self.capacity));
+            pad(&mut batch, len);
+        }
+        batch
+    }
+
+    /// Puts the items of a rejected batch in front of the current one,
+    /// accounting for the memory and weight they took up again. The limits
+    /// are checked with the next item, so a full batch is not validated again
+    /// on its own.
+    fn rebuffer(&mut self, mut batch: B, usage: Usage) {
+        if self.started_at.is_none() {
+            self.started_at = Some(clock::now());
+        }
+        if let Some(ref mut memory) = self.memory {
+            memory.used = memory.used.saturating_add(usage.memory);
+        }
+        if let Some(ref mut weight) = self.weight {
+            weight.used = weight.used.saturating_add(usage.weight);
+        }
+        batch.append(self.items.take());
+        self.items = batch;
+        if self.clock.is_none() && self.mode != FlushMode::CapacityOnly {
+            self.clock = Some(Delay::new(clock::now() + self.duration));
+        }
+    }
+
+    /// Takes the held batch out to be emitted.
+    fn release_held(&mut self) -> Option<B> {
+        let (batch, flushed) = self.held.take()?;
+        self.restore(flushed);
+        Some(batch)
+    }
+
+    /// Returns what is known about the batch that was flushed last.
+    fn flushed(&self) -> Flushed {
+        Flushed {
+            usage: self.taken,
+            reason: self.last_reason,
+            times: self.flushed_at,
+        }
+    }
+
+    /// Reports `flushed` as the batch that was flushed last.
+    fn restore(&mut self, flushed: Flushed) {
+        self.taken = flushed.usage;
+        self.last_reason = flushed.reason;
+        self.flushed_at = flushed.times;
+    }
+
+    fn fits_hard_cap(&self, len: usize) -> bool {
+        len <= self.hard_cap.unwrap_or(usize::MAX)
+    }
+
+    fn poll_absorbing(&mut self) -> Poll<Option<B>, Error<S::Error>> {
+        let min_tail = match self.absorb_small_tail {
+            Some(min_tail) => min_tail,
+            None => return self.poll_batch(),
+        };
+
+        // Every batch is held back until the next one is known, so that a
+        // small final batch can be appended to the one before it.
+        loop {
+            match self.poll_batch() {
+                Ok(Async::Ready(Some(batch))) => {
+                    let last = self.done || self.stream.is_done();
+                    match self.held.take() {
+                        Some((mut prev, flushed))
+                            if last
+                                && batch.len() < min_tail
+                                && self.fits_hard_cap(prev.len() + batch.len()) =>
+                        {
+                            // The merged batch started with the held one.
+                            prev.append(batch);
+                            self.taken = flushed.usage.add(self.taken);
+                            if let (Some((first_item_at, _)), Some((_, emitted_at))) =
+                                (flushed.times, self.flushed_at)
+                            {
+                                self.flushed_at = Some((first_item_at, emitted_at));
+                            }
+                            return Ok(Some(prev).into());
+                        }
+                        Some((prev, flushed)) => {
+                            self.held = Some((batch, self.flushed()));
+                            self.restore(flushed);
+                            return Ok(Some(prev).into());
+                        }
+                        None if last => return Ok(Some(batch).into()),
+                        None => self.held = Some((batch, self.flushed())),
+                    }
+                }
+                Ok(Async::Ready(None)) => return Ok(self.release_held().into()),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => match self.release_held() {
+                    Some(prev) => {
+                        self.err = Some(e);
+                        return Ok(Some(prev).into());
+                    }
+                    None => return Err(e),
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod capacity_tests {
+    use super::*;
+    use futures::{future, stream};
+
+    #[test]
+    fn message_max_memory_without_timeout() {
+        let stream = stream::iter_ok::<_, ()>(vec![1, 2, 3, 4, 5]);
+        let mut chunk_stream = Chunks::with_capacity(stream, 10).max_memory(5, |_| 3);
+
+        let mut batches = Vec::new();
+        while let Async::Ready(Some(batch)) = chunk_stream.poll().unwrap() {
+            batches.push((batch, chunk_stream.last_flush_reason().unwrap()));
+        }
+        assert_eq!(
+            batches,
+            vec![
+                (vec![1, 2], FlushReason::Memory),
+                (vec![3, 4], FlushReason::Memory),
+                (vec![5], FlushReason::End),
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Chunks max memory must be > 0")]
+    fn zero_max_memory_panics() {
+        let stream = stream::iter_ok::<_, ()>(vec![1]);
+        let _ = Chunks::with_capacity(stream, 10).max_memory(0, |_| 1);
+    }
+
+    #[test]
+    fn message_with_capacity() {
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        let mut chunk_stream = Chunks::with_capacity(rx, 3);
+
+        future::lazy(move || {
+            for i in 0..4 {
+                tx.unbounded_send(i).unwrap();
+            }
+            assert_eq!(
+                chunk_stream.poll().unwrap(),
+                Async::Ready(Some(vec![0, 1, 2]))
+            );
+            // No timer is armed for the partial batch.
+            assert!(chunk_stream.poll().unwrap().is_not_ready());
+            assert!(!chunk_stream.is_timer_armed());
+
+            tx.unbounded_send(4).unwrap();
+            drop(tx);
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(Some(vec![3, 4])));
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(None));
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+
+    #[cfg(not(feature = "timer"))]
+    #[test]
+    #[should_panic(expected = "FlushMode::Hybrid requires the `timer` feature")]
+    fn set_mode_without_timer() {
+        let stream = stream::iter_ok::<_, ()>(vec![1]);
+        Chunks::with_capacity(stream, 2).set_mode(FlushMode::Hybrid);
+    }
+}
+
+#[cfg(all(test, feature = "timer"))]
+mod tests {
+    use super::*;
+    use futures::{future, stream, try_ready};
+    use std::io;
+    use std::iter;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn messages_pass_through() {
+        let iter = iter::once(5);
+        let stream = stream::iter_ok::<_, io::Error>(iter);
+
+        let chunk_stream = Chunks::new(stream, 5, Duration::new(10, 0));
+
+        let v = chunk_stream.collect();
+        tokio::run(v.then(|res| {
+            match res {
+                Err(e) => panic!("{:?}", e),
+                Ok(v) => assert_eq!(vec![vec![5]], v),
+            };
+            Ok(())
+        }));
+    }
+
+    #[test]
+    fn message_chunks() {
+        let iter = vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9].into_iter();
+        let stream = stream::iter_ok::<_, io::Error>(iter);
+
+        let chunk_stream = Chunks::new(stream, 5, Duration::new(10, 0));
+
+        let v = chunk_stream.collect();
+        tokio::run(v.then(|res| {
+            match res {
+                Err(e) => panic!("{:?}", e),
+                Ok(v) => assert_eq!(vec![vec![0, 1, 2, 3, 4], vec![5, 6, 7, 8, 9]], v),
+            };
+            Ok(())
+        }));
+    }
+
+    #[test]
+    fn message_early_exit() {
+        let iter = vec![1, 2, 3, 4].into_iter();
+        let stream = stream::iter_ok::<_, io::Error>(iter);
+
+        let chunk_stream = Chunks::new(stream, 5, Duration::new(100, 0));
+
+        let v = chunk_stream.collect();
+        tokio::run(v.then(|res| {
+            match res {
+                Err(e) => panic!("{:?}", e),
+                Ok(v) => assert_eq!(vec![vec![1, 2, 3, 4]], v),
+            };
+            Ok(())
+        }));
+    }
+
+    #[test]
+    fn message_timeout() {
+        let iter = vec![1, 2, 3, 4].into_iter();
+        let stream0 = stream::iter_ok::<_, io::Error>(iter);
+
+        let iter = vec![5].into_iter();
+        let stream1 = stream::iter_ok::<_, io::Error>(iter).and_then(|n| {
+            Delay::new(Instant::now() + Duration::from_millis(300))
+                .and_then(move |_| Ok(n))
+                .map_err(|e| io::Error::new(io::ErrorKind::TimedOut, e))
+        });
+
+        let iter = vec![6, 7, 8].into_iter();
+        let stream2 = stream::iter_ok::<_, io::Error>(iter);
+
+        let stream = stream0.chain(stream1).chain(stream2);
+        let chunk_stream = Chunks::new(stream, 5, Duration::from_millis(100));
+
+        let now = Instant::now();
+        let min_times = [Duration::from_millis(80), Duration::from_millis(150)];
+        let max_times = [Duration::from_millis(280), Duration::from_millis(350)];
+        let results = vec![vec![1, 2, 3, 4], vec![5, 6, 7, 8]];
+        let mut i = 0;
+
+        let v = chunk_stream
+            .map(move |s| {
+                let now2 = Instant::now();
+                println!("{:?}", now2 - now);
+                assert!((now2 - now) < max_times[i]);
+                assert!((now2 - now) > min_times[i]);
+                i += 1;
+                s
+            })
+            .collect();
+
+        tokio::run(v.then(move |res| {
+            match res {
+                Err(e) => panic!("{:?}", e),
+                Ok(v) => assert_eq!(v, results),
+            };
+            Ok(())
+        }));
+    }
+
+    #[test]
+    fn message_item_timeout() {
+        let iter = vec![(1, Duration::from_secs(10))].into_iter();
+        let stream0 = stream::iter_ok::<_, io::Error>(iter);
+
+        let iter = vec![(2, Duration::from_millis(100))].into_iter();
+        let stream1 = stream::iter_ok::<_, io::Error>(iter).and_then(|n| {
+            Delay::new(Instant::now() + Duration::from_millis(100))
+                .and_then(move |_| Ok(n))
+                .map_err(|e| io::Error::new(io::ErrorKind::TimedOut, e))
+        });
+
+        let iter = vec![(3, Duration::from_secs(10))].into_iter();
+        let stream2 = stream::iter_ok::<_, io::Error>(iter).and_then(|n| {
+            Delay::new(Instant::now() + Duration::from_secs(5))
+                .and_then(move |_| Ok(n))
+                .map_err(|e| io::Error::new(io::ErrorKind::TimedOut, e))
+        });
+
+        let stream = stream0.chain(stream1).chain(stream2);
+        let chunk_stream =
+            Chunks::new(stream, 5, Duration::from_secs(10)).item_timeout(|&(_, timeout)| timeout);
+
+        let now = Instant::now();
+        let v = chunk_stream.into_future();
+        tokio::run(v.then(move |res| {
+            match res {
+                Err((e, _)) => panic!("{:?}", e),
+                Ok((v, _)) => {
+                    let elapsed = Instant::now() - now;
+                    assert!(elapsed > Duration::from_millis(150));
+                    assert!(elapsed < Duration::from_millis(1000));
+                    let v: Vec<_> = v.unwrap().into_iter().map(|(n, _)| n).collect();
+                    assert_eq!(v, vec![1, 2]);
+                }
+            };
+            Ok(())
+        }));
+    }
+
+    #[test]
+    fn message_snapshot() {
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        (1..4).for_each(|i| tx.unbounded_send(i).unwrap());
+
+        let mut chunk_stream = Chunks::new(rx, 5, Duration::new(10, 0));
+
+        tokio::run(future::lazy(move || {
+            assert!(chunk_stream.poll().unwrap().is_not_ready());
+            let snapshot = chunk_stream.snapshot();
+            assert_eq!(snapshot, vec![1, 2, 3]);
+
+            tx.unbounded_send(4).unwrap();
+            drop(tx);
+            assert_eq!(snapshot, vec![1, 2, 3]);
+            assert_eq!(
+                chunk_stream.poll().unwrap(),
+                Async::Ready(Some(vec![1, 2, 3, 4]))
+            );
+            assert_eq!(snapshot, vec![1, 2, 3]);
+            Ok(())
+        }));
+    }
+
+    #[test]
+    fn message_timeout_stalled_stream() {
+        let iter = vec![1, 2, 3].into_iter();
+        let stream =
+            stream::iter_ok::<_, io::Error>(iter).chain(stream::poll_fn(|| Ok(Async::NotReady)));
+
+        let chunk_stream = Chunks::new(stream, 5, Duration::from_millis(100));
+
+        let now = Instant::now();
+        let v = chunk_stream.into_future();
+        tokio::run(v.then(move |res| {
+            match res {
+                Err((e, _)) => panic!("{:?}", e),
+                Ok((v, _)) => {
+                    assert!(Instant::now() - now >= Duration::from_millis(100));
+                    assert_eq!(v, Some(vec![1, 2, 3]));
+                }
+            };
+            Ok(())
+        }));
+    }
+
+    #[test]
+    fn message_pull_rate_limit() {
+        let pulled = Arc::new(Mutex::new(Vec::new()));
+        let pulled2 = pulled.clone();
+
+        let iter = vec![1, 2, 3, 4, 5, 6, 7].into_iter();
+        let stream = stream::iter_ok::<_, io::Error>(iter)
+            .inspect(move |_| pulled2.lock().unwrap().push(Instant::now()));
+
+        let chunk_stream = Chunks::new(stream, 10, Duration::new(10, 0))
+            .pull_rate_limit(3, Duration::from_millis(200));
+
+        let v = chunk_stream.collect();
+        tokio::run(v.then(|res| {
+            match res {
+                Err(e) => panic!("{:?}", e),
+                Ok(v) => assert_eq!(vec![vec![1, 2, 3, 4, 5, 6, 7]], v),
+            };
+            Ok(())
+        }));
+
+        let pulled = pulled.lock().unwrap();
+        assert_eq!(pulled.len(), 7);
+        for (first, fourth) in pulled.iter().zip(pulled.iter().skip(3)) {
+            assert!(*fourth - *first >= Duration::from_millis(195));
+        }
+    }
+
+    #[test]
+    fn message_unbounded_pull_rate_limit() {
+        let stream = stream::iter_ok::<_, io::Error>(vec![1, 2, 3]);
+        let chunk_stream = Chunks::new(stream, 2, Duration::new(10, 0))
+            .pull_rate_limit(usize::MAX, Duration::from_secs(1));
+
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        assert_eq!(v, vec![vec![1, 2], vec![3]]);
+    }
+
+    #[test]
+    fn message_chunks_boxed() {
+        let iter = vec![0, 1, 2, 3, 4, 5, 6].into_iter();
+        let stream = stream::iter_ok::<_, io::Error>(iter);
+
+        let chunk_stream = Chunks::new(stream, 5, Duration::new(10, 0)).boxed();
+
+        let v = chunk_stream.collect();
+        tokio::run(v.then(|res| {
+            match res {
+                Err(e) => panic!("{:?}", e),
+                Ok(v) => assert_eq!(vec![vec![0, 1, 2, 3, 4], vec![5, 6]], v),
+            };
+            Ok(())
+        }));
+    }
+
+    #[test]
+    fn message_chunks_boxed_local() {
+        use std::rc::Rc;
+
+        let iter = vec![0, 1, 2, 3, 4, 5, 6].into_iter().map(Rc::new);
+        let stream = stream::iter_ok::<_, io::Error>(iter);
+
+        let chunk_stream = Chunks::new(stream, 5, Duration::new(10, 0)).boxed_local();
+
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        let v: Vec<Vec<i32>> = v
+            .into_iter()
+            .map(|b| b.iter().map(|n| **n).collect())
+            .collect();
+        assert_eq!(v, vec![vec![0, 1, 2, 3, 4], vec![5, 6]]);
+    }
+
+    #[test]
+    fn message_shutdown_on() {
+        let (tx, rx) = oneshot::channel();
+
+        let iter = vec![1, 2].into_iter();
+        let stream =
+            stream::iter_ok::<_, io::Error>(iter).chain(stream::poll_fn(|| Ok(Async::NotReady)));
+
+        let mut chunk_stream = Chunks::new(stream, 5, Duration::new(10, 0)).shutdown_on(rx);
+
+        tokio::run(future::lazy(move || {
+            assert!(chunk_stream.poll().unwrap().is_not_ready());
+            tx.send(()).unwrap();
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(Some(vec![1, 2])));
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(None));
+            Ok(())
+        }));
+    }
+
+    #[test]
+    fn message_shutdown_cancel() {
+        let (tx, rx) = oneshot::channel::<()>();
+        drop(tx);
+
+        let stream = stream::poll_fn(|| Ok::<_, io::Error>(Async::Ready(Some(1))));
+        let mut chunk_stream = Chunks::new(stream, 2, Duration::new(10, 0))
+            .shutdown_on(rx)
+            .ignore_shutdown_cancel();
+
+        tokio::run(future::lazy(move || {
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(Some(vec![1, 1])));
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(Some(vec![1, 1])));
+            Ok(())
+        }));
+
+        let (tx, rx) = oneshot::channel::<()>();
+        drop(tx);
+
+        let stream = stream::poll_fn(|| Ok::<_, io::Error>(Async::Ready(Some(1))));
+        let mut chunk_stream = Chunks::new(stream, 2, Duration::new(10, 0)).shutdown_on(rx);
+
+        tokio::run(future::lazy(move || {
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(None));
+            Ok(())
+        }));
+    }
+
+    fn missing_clock() -> impl Future<Item = (), Error = ()> {
+        let stream = stream::poll_fn(|| Ok::<Async<Option<i32>>, io::Error>(Async::NotReady));
+
+        // Buffer an item behind the back of the combinator, so that there is
+        // no timer for it.
+        let mut chunk_stream = Chunks::new(stream, 5, Duration::from_millis(50));
+        chunk_stream.items.push(1);
+
+        chunk_stream.into_future().then(|res| {
+            match res {
+                Err((e, _)) => panic!("{:?}", e),
+                Ok((v, _)) => assert_eq!(v, Some(vec![1])),
+            };
+            Ok(())
+        })
+    }
+
+    #[test]
+    #[cfg(not(debug_assertions))]
+    fn message_missing_clock_rearms() {
+        tokio::run(missing_clock());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "no clock but there are items")]
+    fn message_missing_clock_asserts() {
+        tokio::runtime::current_thread::block_on_all(missing_clock()).unwrap();
+    }
+
+    #[test]
+    fn message_growth_policy() {
+        let iter = vec![0, 1, 2, 3, 4, 5, 6].into_iter();
+        let stream = stream::iter_ok::<_, io::Error>(iter);
+
+        let mut chunk_stream =
+            Chunks::new(stream, 5, Duration::new(10, 0)).growth_policy(GrowthPolicy::Doubling);
+        assert_eq!(chunk_stream.items.capacity(), 0);
+
+        chunk_stream.reserve(3);
+        assert!(chunk_stream.items.capacity() >= 3);
+
+        let chunk_stream = chunk_stream.growth_policy(GrowthPolicy::FixedStep(2));
+        assert_eq!(chunk_stream.items.capacity(), 2);
+
+        let v = chunk_stream.collect();
+        tokio::run(v.then(|res| {
+            match res {
+                Err(e) => panic!("{:?}", e),
+                Ok(v) => assert_eq!(vec![vec![0, 1, 2, 3, 4], vec![5, 6]], v),
+            };
+            Ok(())
+        }));
+    }
+
+    #[test]
+    fn message_next_flush_will_be_timeout() {
+        let iter = vec![1, 2].into_iter();
+        let stream =
+            stream::iter_ok::<_, io::Error>(iter).chain(stream::poll_fn(|| Ok(Async::NotReady)));
+
+        let mut chunk_stream = Chunks::new(stream, 5, Duration::new(10, 0));
+        assert!(!chunk_stream.next_flush_will_be_timeout());
+        assert_eq!(chunk_stream.time_until_flush(), None);
+
+        tokio::run(future::lazy(move || {
+            assert!(chunk_stream.poll().unwrap().is_not_ready());
+            assert!(chunk_stream.next_flush_will_be_timeout());
+            assert!(chunk_stream.time_until_flush().unwrap() <= Duration::new(10, 0));
+            Ok(())
+        }));
+    }
+
+    #[test]
+    fn message_borrowed_items() {
+        let lines: Vec<String> = vec!["a".into(), "bb".into(), "ccc".into()];
+        let stream = stream::iter_ok::<_, io::Error>(lines.iter().map(|s| s.as_str()));
+
+        let chunk_stream = Chunks::new(stream, 2, Duration::new(10, 0))
+            .item_timeout(|s: &&str| Duration::from_secs(s.len() as u64));
+
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        assert_eq!(v, vec![vec!["a", "bb"], vec!["ccc"]]);
+    }
+
+    #[test]
+    fn message_flush_on_sentinel() {
+        let input = vec![1, 2, 0, 3, 0, 0, 4, 5, 6];
+
+        for &(mode, ref expected) in &[
+            (
+                Sentinel::Include,
+                vec![vec![1, 2, 0], vec![3, 0], vec![0], vec![4, 5, 6]],
+            ),
+            (Sentinel::Drop, vec![vec![1, 2], vec![3], vec![4, 5, 6]]),
+        ] {
+            let stream = stream::iter_ok::<_, io::Error>(input.clone());
+            let chunk_stream = Chunks::new(stream, 5, Duration::new(10, 0)).flush_on(0, mode);
+
+            let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+            assert_eq!(&v, expected);
+        }
+    }
+
+    #[test]
+    fn message_absorb_small_tail() {
+        let iter = vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10].into_iter();
+        let stream = stream::iter_ok::<_, io::Error>(iter);
+
+        let chunk_stream = Chunks::new(stream, 5, Duration::new(10, 0)).absorb_small_tail(2);
+
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        assert_eq!(v, vec![vec![0, 1, 2, 3, 4], vec![5, 6, 7, 8, 9, 10]]);
+
+        let iter = vec![0, 1, 2, 3, 4, 5, 6, 7].into_iter();
+        let stream = stream::iter_ok::<_, io::Error>(iter);
+
+        let chunk_stream = Chunks::new(stream, 5, Duration::new(10, 0)).absorb_small_tail(2);
+
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        assert_eq!(v, vec![vec![0, 1, 2, 3, 4], vec![5, 6, 7]]);
+    }
+
+    #[test]
+    fn build_errors() {
+        let stream = || stream::iter_ok::<_, io::Error>(vec![1, 2, 3]);
+
+        let err = Chunks::try_new(stream(), 0, Duration::new(10, 0)).err();
+        assert_eq!(err, Some(BuildError::InvalidCapacity(0)));
+        assert_eq!(err.unwrap().to_string(), "capacity must be > 0, got 0");
+
+        let err = Chunks::new(stream(), 5, Duration::new(10, 0))
+            .try_pull_rate_limit(0, Duration::from_secs(1))
+            .err();
+        assert_eq!(
+            err,
+            Some(BuildError::InvalidPullRate {
+                max_items: 0,
+                per: Duration::from_secs(1)
+            })
+        );
+        assert_eq!(
+            err.unwrap().to_string(),
+            "pull rate must allow at least one item per non-empty period, got 0 per 1s"
+        );
+
+        let err = Chunks::new(stream(), 5, Duration::new(10, 0))
+            .try_pull_rate_limit(3, Duration::new(0, 0))
+            .err();
+        assert_eq!(
+            err,
+            Some(BuildError::InvalidPullRate {
+                max_items: 3,
+                per: Duration::new(0, 0)
+            })
+        );
+
+        let err = Chunks::new(stream(), 5, Duration::new(10, 0))
+            .try_growth_policy(GrowthPolicy::FixedStep(0))
+            .err();
+        assert_eq!(err, Some(BuildError::InvalidGrowthStep(0)));
+        assert_eq!(err.unwrap().to_string(), "growth step must be > 0, got 0");
+
+        let inconsistent = |field, detail: &str| {
+            Some(BuildError::Inconsistent {
+                field,
+                detail: detail.to_string(),
+            })
+        };
+        let err = Chunks::new(stream(), 5, Duration::new(10, 0))
+            .soft_flush_at(6)
+            .build()
+            .err();
+        assert_eq!(
+            err,
+            inconsistent("soft_flush_at", "6 exceeds the capacity 5")
+        );
+        assert_eq!(
+            err.unwrap().to_string(),
+            "inconsistent `soft_flush_at`: 6 exceeds the capacity 5"
+        );
+
+        let err = Chunks::new(stream(), 5, Duration::new(10, 0))
+            .absorb_small_tail(8)
+            .build()
+            .err();
+        assert_eq!(
+            err,
+            inconsistent("absorb_small_tail", "8 exceeds the capacity 5")
+        );
+
+        let err = Chunks::new(stream(), 5, Duration::new(10, 0))
+            .hard_cap(4)
+            .build()
+            .err();
+        assert_eq!(err, inconsistent("hard_cap", "4 is below the capacity 5"));
+
+        // The capacity does not bound the batches of these modes, so the hard
+        // cap is the only limit on their size.
+        assert!(
+            Chunks::weighted(stream(), 100, Duration::new(10, 0), |&n| n)
+                .hard_cap(4)
+                .build()
+                .is_ok()
+        );
+        let mut interval = Chunks::new(stream(), 5, Duration::new(10, 0)).hard_cap(4);
+        interval.set_mode(FlushMode::Interval);
+        assert!(interval.build().is_ok());
+
+        assert!(Chunks::new(stream(), 5, Duration::new(10, 0))
+            .soft_flush_at(5)
+            .absorb_small_tail(2)
+            .hard_cap(5)
+            .build()
+            .is_ok());
+
+        assert!(Chunks::try_new(stream(), 5, Duration::new(10, 0))
+            .unwrap()
+            .try_pull_rate_limit(3, Duration::from_secs(1))
+            .unwrap()
+            .try_growth_policy(GrowthPolicy::Doubling)
+            .is_ok());
+    }
+
+    #[test]
+    fn message_from_streams() {
+        let stream0 = stream::iter_ok::<_, io::Error>(vec![1, 2, 3]);
+        let stream1 = stream::iter_ok::<_, io::Error>(vec![10, 20]);
+
+        let chunk_stream = Chunks::from_streams(vec![stream0, stream1], 2, Duration::new(10, 0));
+
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        assert_eq!(v, vec![vec![1, 10], vec![2, 20], vec![3]]);
+    }
+
+    #[test]
+    fn message_chunks_unchecked() {
+        let iter = vec![0, 1, 2, 3, 4, 5, 6].into_iter();
+        let stream = stream::iter_ok::<_, io::Error>(iter);
+
+        let chunk_stream = Chunks::new_unchecked(stream, 5, Duration::new(10, 0));
+
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        assert_eq!(v, vec![vec![0, 1, 2, 3, 4], vec![5, 6]]);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "inner stream polled after completion")]
+    fn message_chunks_unchecked_misuse() {
+        let stream = stream::iter_ok::<_, io::Error>(vec![1]);
+        let mut chunk_stream = Chunks::new_unchecked(stream, 5, Duration::new(10, 0));
+
+        tokio::runtime::current_thread::block_on_all(future::lazy(move || {
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(Some(vec![1])));
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(None));
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(None));
+            chunk_stream.stream.poll()
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn message_take_counters() {
+        let stream = stream::iter_ok::<_, io::Error>(vec![1, 2, 3, 4, 5, 6, 7])
+            .chain(stream::poll_fn(|| Ok(Async::NotReady)));
+
+        let mut chunk_stream = Chunks::new(stream, 3, Duration::from_millis(50));
+
+        tokio::runtime::current_thread::block_on_all(future::lazy(move || {
+            assert_eq!(
+                chunk_stream.poll().unwrap(),
+                Async::Ready(Some(vec![1, 2, 3]))
+            );
+            assert_eq!(
+                chunk_stream.take_counters(),
+                Counters {
+                    items: 3,
+                    batches: 1,
+                    timeouts: 0,
+                    capacity_flushes: 1,
+                }
+            );
+
+            assert_eq!(
+                chunk_stream.poll().unwrap(),
+                Async::Ready(Some(vec![4, 5, 6]))
+            );
+            chunk_stream.into_future().map(|(v, mut chunk_stream)| {
+                assert_eq!(v, Some(vec![7]));
+                assert_eq!(
+                    chunk_stream.take_counters(),
+                    Counters {
+                        items: 4,
+                        batches: 2,
+                        timeouts: 1,
+                        capacity_flushes: 1,
+                    }
+                );
+                assert_eq!(chunk_stream.counters(), Counters::default());
+            })
+        }))
+        .map_err(|(e, _)| e)
+        .unwrap();
+    }
+
+    #[test]
+    fn message_pad_to_capacity() {
+        let stream = stream::iter_ok::<_, io::Error>(vec![1, 2])
+            .chain(stream::poll_fn(|| Ok(Async::NotReady)));
+
+        let chunk_stream = Chunks::new(stream, 5, Duration::from_millis(50)).pad_to_capacity();
+
+        let (v, chunk_stream) =
+            tokio::runtime::current_thread::block_on_all(chunk_stream.into_future())
+                .map_err(|(e, _)| e)
+                .unwrap();
+        assert_eq!(v, Some(vec![1, 2, 0, 0, 0]));
+        assert_eq!(chunk_stream.unpadded_len(), 2);
+    }
+
+    #[test]
+    fn message_sort_by_key() {
+        let iter = vec![(3, 'a'), (1, 'b'), (3, 'c'), (2, 'd'), (0, 'e'), (1, 'f')].into_iter();
+        let stream = stream::iter_ok::<_, io::Error>(iter);
+
+        let chunk_stream = Chunks::new(stream, 4, Duration::new(10, 0)).sort_by_key(|&(k, _)| k);
+
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        assert_eq!(
+            v,
+            vec![
+                vec![(1, 'b'), (2, 'd'), (3, 'a'), (3, 'c')],
+                vec![(0, 'e'), (1, 'f')],
+            ]
+        );
+    }
+
+    #[test]
+    fn message_progress_every() {
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let reported = progress.clone();
+
+        let stream = stream::iter_ok::<_, io::Error>(0..23);
+        let chunk_stream = Chunks::new(stream, 10, Duration::new(10, 0))
+            .progress_every(5, move |len| reported.lock().unwrap().push(len));
+
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        assert_eq!(v.len(), 3);
+        assert_eq!(*progress.lock().unwrap(), vec![5, 10, 5, 10]);
+    }
+
+    #[test]
+    fn message_prioritize() {
+        let iter = vec![(3, 'a'), (1, 'B'), (2, 'c'), (0, 'D'), (1, 'e'), (2, 'F')].into_iter();
+        let stream = stream::iter_ok::<_, io::Error>(iter);
+
+        let chunk_stream = Chunks::new(stream, 4, Duration::new(10, 0))
+            .sort_by_key(|&(k, _)| k)
+            .prioritize(|&(_, c): &(u32, char)| {
+                if c.is_uppercase() {
+                    Priority::High
+                } else {
+                    Priority::Low
+                }
+            });
+
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        assert_eq!(
+            v,
+            vec![
+                vec![(0, 'D'), (1, 'B'), (2, 'c'), (3, 'a')],
+                vec![(2, 'F'), (1, 'e')],
+            ]
+        );
+    }
+
+    #[test]
+    fn message_pausable() {
+        let pulled = Arc::new(Mutex::new(0));
+        let pulled2 = pulled.clone();
+
+        let iter = vec![0, 1, 2, 3, 4, 5].into_iter();
+        let stream =
+            stream::iter_ok::<_, io::Error>(iter).inspect(move |_| *pulled2.lock().unwrap() += 1);
+
+        let (mut chunk_stream, handle) = Chunks::pausable(stream, 3, Duration::new(10, 0));
+
+        let rest = tokio::runtime::current_thread::block_on_all(future::lazy(move || {
+            assert_eq!(
+                chunk_stream.poll().unwrap(),
+                Async::Ready(Some(vec![0, 1, 2]))
+            );
+
+            handle.pause();
+            assert!(chunk_stream.poll().unwrap().is_not_ready());
+            assert_eq!(*pulled.lock().unwrap(), 3);
+
+            handle.resume();
+            chunk_stream.collect()
+        }))
+        .unwrap();
+        assert_eq!(rest, vec![vec![3, 4, 5]]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Chunks capacity must be > 0, got 0")]
+    fn zero_capacity_panics() {
+        let stream = stream::iter_ok::<_, io::Error>(vec![1]);
+        let _ = Chunks::new(stream, 0, Duration::new(10, 0));
+    }
+
+    #[test]
+    fn message_distinct() {
+        let iter = vec![1, 2, 1, 3, 2].into_iter();
+        let stream = stream::iter_ok::<_, io::Error>(iter);
+
+        let chunk_stream = Chunks::distinct(stream, 10, Duration::new(10, 0));
+
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        assert_eq!(v, vec![vec![1, 2, 3]]);
+
+        let iter = vec![1, 1, 2, 1, 3, 1].into_iter();
+        let stream = stream::iter_ok::<_, io::Error>(iter);
+
+        let chunk_stream = Chunks::distinct(stream, 2, Duration::new(10, 0));
+
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        assert_eq!(v, vec![vec![1, 2], vec![1, 3], vec![1]]);
+    }
+
+    #[test]
+    fn message_max_memory() {
+        let pulled = Arc::new(Mutex::new(0));
+        let pulled2 = pulled.clone();
+
+        let iter = vec![6, 6, 1].into_iter();
+        let stream =
+            stream::iter_ok::<_, io::Error>(iter).inspect(move |_| *pulled2.lock().unwrap() += 1);
+
+        let chunk_stream =
+            Chunks::new(stream, 10, Duration::from_millis(50)).max_memory(10, |&n| n);
+
+        let (v, chunk_stream) =
+            tokio::runtime::current_thread::block_on_all(chunk_stream.into_future())
+                .map_err(|(e, _)| e)
+                .unwrap();
+        assert_eq!(v, Some(vec![6, 6]));
+        assert_eq!(*pulled.lock().unwrap(), 2);
+
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        assert_eq!(v, vec![vec![1]]);
+    }
+
+    #[test]
+    fn message_replace_stream() {
+        let stream: Box<dyn Stream<Item = i32, Error = io::Error> + Send> =
+            Box::new(stream::iter_ok(vec![1, 2]).chain(stream::poll_fn(|| Ok(Async::NotReady))));
+
+        let mut chunk_stream = Chunks::new(stream, 4, Duration::new(10, 0));
+
+        let v = tokio::runtime::current_thread::block_on_all(future::lazy(move || {
+            assert!(chunk_stream.poll().unwrap().is_not_ready());
+
+            let _old = chunk_stream.replace_stream(Box::new(stream::iter_ok(vec![3, 4, 5])));
+            chunk_stream.collect()
+        }))
+        .unwrap();
+        assert_eq!(v, vec![vec![1, 2, 3, 4], vec![5]]);
+    }
+
+    #[test]
+    fn message_reset_partial_batch() {
+        type Source = Box<dyn Stream<Item = u32, Error = ()> + Send>;
+
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        let mut chunk_stream =
+            Chunks::new(Box::new(rx) as Source, 5, Duration::new(10, 0)).max_memory(40, |_| 10);
+
+        let v = tokio::runtime::current_thread::block_on_all(future::lazy(move || {
+            tx.unbounded_send(1).unwrap();
+            tx.unbounded_send(2).unwrap();
+            assert!(chunk_stream.poll().unwrap().is_not_ready());
+
+            // The partial batch of the old stream is dropped, and so is the
+            // memory it took up.
+            let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+            chunk_stream.reset(Box::new(rx));
+            (7..10).for_each(|i| tx.unbounded_send(i).unwrap());
+            drop(tx);
+            chunk_stream.collect()
+        }))
+        .unwrap();
+        assert_eq!(v, vec![vec![7, 8, 9]]);
+    }
+
+    #[test]
+    fn message_reset() {
+        let mut chunk_stream = Chunks::new(
+            stream::iter_result(vec![Ok(1), Ok(2), Ok(3), Err(io::Error::other("bad"))]),
+            2,
+            Duration::new(10, 0),
+        );
+
+        future::lazy(move || {
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(Some(vec![1, 2])));
+            // The error is deferred until the partial batch was emitted.
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(Some(vec![3])));
+
+            chunk_stream.reset(stream::iter_result(vec![Ok(4), Ok(5), Ok(6)]));
+            assert!(!chunk_stream.is_timer_armed());
+            assert!(chunk_stream.snapshot().is_empty());
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(Some(vec![4, 5])));
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(Some(vec![6])));
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(None));
+
+            // A completed combinator starts over as well.
+            chunk_stream.reset(stream::iter_result(vec![Ok(7)]));
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(Some(vec![7])));
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(None));
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+
+    #[test]
+    fn message_grace() {
+        let late = Delay::new(Instant::now() + Duration::from_millis(70))
+            .then(|_| Ok::<_, io::Error>(9))
+            .into_stream();
+        let stream = stream::iter_ok::<_, io::Error>(0..9).chain(late);
+
+        let chunk_stream = Chunks::new(stream, 10, Duration::from_millis(50))
+            .grace(0.9, Duration::from_millis(40));
+
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        assert_eq!(v, vec![(0..10).collect::<Vec<_>>()]);
+    }
+
+    #[test]
+    fn message_new_ms() {
+        let stalled = || {
+            stream::iter_ok::<_, io::Error>(vec![1, 2, 3])
+                .chain(stream::poll_fn(|| Ok(Async::NotReady)))
+        };
+
+        let by_ms = Chunks::new_ms(stalled(), 5, 100);
+        let by_duration = Chunks::new(stalled(), 5, Duration::from_millis(100));
+        assert_eq!(by_ms.duration, by_duration.duration);
+
+        let now = Instant::now();
+        let (v, _) = tokio::runtime::current_thread::block_on_all(by_ms.into_future())
+            .map_err(|(e, _)| e)
+            .unwrap();
+        assert!(Instant::now() - now >= Duration::from_millis(100));
+        assert_eq!(v, Some(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn message_weighted() {
+        let stream = stream::iter_ok::<_, io::Error>(vec!["ab", "cd", "efg", "h", "ij"]);
+
+        let chunk_stream = Chunks::weighted(stream, 4, Duration::new(10, 0), |s| s.len());
+
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        assert_eq!(v, vec![vec!["ab", "cd"], vec!["efg", "h"], vec!["ij"]]);
+
+        let stream = stream::iter_ok::<_, io::Error>(vec![4, 4, 4]);
+        let chunk_stream = Chunks::weighted(stream, 8, Duration::new(10, 0), |&n| n);
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        assert_eq!(v, vec![vec![4, 4], vec![4]]);
+
+        // An item that does not fit starts the next batch instead of pushing
+        // the current one over the limit.
+        let stream = stream::iter_ok::<_, io::Error>(vec![5, 2, 5, 9, 1]);
+        let chunk_stream = Chunks::weighted(stream, 8, Duration::new(10, 0), |&n| n);
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        assert_eq!(v, vec![vec![5, 2], vec![5], vec![9], vec![1]]);
+    }
+
+    #[test]
+    fn message_weight_target() {
+        let weights = |v: Vec<Vec<usize>>| v.iter().map(|b| b.iter().sum()).collect::<Vec<usize>>();
+
+        let stream = stream::iter_ok::<_, io::Error>(vec![3, 3, 3, 3]);
+        let chunk_stream =
+            Chunks::weighted(stream, 100, Duration::new(10, 0), |&n| n).weight_target(7, 1);
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        assert_eq!(v, vec![vec![3, 3], vec![3, 3]]);
+
+        // A batch overshoots the target within the tolerance, and an oversized
+        // item is emitted on its own.
+        let stream = stream::iter_ok::<_, io::Error>(vec![4, 4, 2, 3, 3, 12, 1, 6]);
+        let chunk_stream =
+            Chunks::weighted(stream, 100, Duration::new(10, 0), |&n| n).weight_target(7, 1);
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        assert_eq!(v, vec![vec![4, 4], vec![2, 3, 3], vec![12], vec![1, 6]]);
+        assert_eq!(weights(v), vec![8, 8, 12, 7]);
+    }
+
+    #[test]
+    fn message_try_weighted() {
+        let stream = stream::iter_ok::<_, io::Error>(vec![1, 2, -1, 3]);
+
+        let mut chunk_stream = Chunks::try_weighted(stream, 10, Duration::new(10, 0), |&n| {
+            if n < 0 {
+                Err("negative weight")
+            } else {
+                Ok(n as usize)
+            }
+        })
+        .wait();
+
+        assert_eq!(chunk_stream.next().unwrap().unwrap(), vec![1, 2]);
+        let e = chunk_stream.next().unwrap().unwrap_err();
+        assert!(e.is_weight());
+        assert_eq!(e.into_weight().unwrap().to_string(), "negative weight");
+        assert_eq!(chunk_stream.next().unwrap().unwrap(), vec![3]);
+        assert!(chunk_stream.next().is_none());
+    }
+
+    #[test]
+    fn message_retry_inner() {
+        let broken = || io::Error::other("flaky source");
+        let stream = stream::iter_result(vec![
+            Ok(1),
+            Err(broken()),
+            Err(broken()),
+            Ok(2),
+            Ok(3),
+            Err(broken()),
+            Ok(4),
+        ]);
+
+        let chunk_stream = Chunks::new(stream, 2, Duration::new(10, 0)).retry_inner(3);
+
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        assert_eq!(v, vec![vec![1], vec![2, 3], vec![4]]);
+
+        let stream = stream::iter_result(vec![Ok(1), Err(broken()), Err(broken()), Ok(2)]);
+
+        let mut chunk_stream = Chunks::new(stream, 2, Duration::new(10, 0))
+            .retry_inner(1)
+            .wait();
+
+        assert_eq!(chunk_stream.next().unwrap().unwrap(), vec![1]);
+        assert_eq!(
+            chunk_stream.get_ref().last_flush_reason(),
+            Some(FlushReason::Retry)
+        );
+        assert!(chunk_stream.next().unwrap().unwrap_err().is_inner());
+        assert_eq!(chunk_stream.next().unwrap().unwrap(), vec![2]);
+    }
+
+    #[test]
+    fn message_inspect_item() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        let stream = stream::iter_ok::<_, io::Error>(vec![1, 1, 2, 3, 3, 4, 5]);
+        let chunk_stream = Chunks::distinct(stream, 2, Duration::new(10, 0))
+            .inspect_item(move |&item| log.lock().unwrap().push(item));
+
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        assert_eq!(v, vec![vec![1, 2], vec![3, 4], vec![5]]);
+        // The duplicates that were dropped are not inspected.
+        assert_eq!(*seen.lock().unwrap(), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn message_hard_cap() {
+        let stream = stream::iter_ok::<_, io::Error>(0..40);
+        let chunk_stream = Chunks::new(stream, 1, Duration::new(10, 0))
+            .capacity_schedule(|batch| 2 + 3 * batch as usize)
+            .hard_cap(6);
+
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        let sizes: Vec<_> = v.iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![2, 5, 6, 6, 6, 6, 6, 3]);
+
+        // The cap also holds for a mode that ignores the capacity.
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        let mut chunk_stream = Chunks::new(rx, 100, Duration::new(10, 0)).hard_cap(2);
+        chunk_stream.set_mode(FlushMode::Interval);
+        tokio::runtime::current_thread::block_on_all(future::lazy(move || {
+            for i in 0..3 {
+                tx.unbounded_send(i).unwrap();
+            }
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(Some(vec![0, 1])));
+            assert_eq!(chunk_stream.last_flush_reason(), Some(FlushReason::HardCap));
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+
+        // And for a weighted one, after it was checked by `build`.
+        let stream = stream::iter_ok::<_, io::Error>(vec![1, 1, 1, 5, 1]);
+        let chunk_stream = Chunks::weighted(stream, 6, Duration::new(10, 0), |&n| n)
+            .hard_cap(2)
+            .build()
+            .unwrap();
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        assert_eq!(v, vec![vec![1, 1], vec![1, 5], vec![1]]);
+    }
+
+    #[test]
+    fn message_hold_on_error() {
+        let broken = || io::Error::other("flaky source");
+        let stream = stream::iter_result(vec![
+            Ok(1),
+            Err(broken()),
+            Ok(2),
+            Ok(3),
+            Err(broken()),
+            Err(broken()),
+            Ok(4),
+        ]);
+
+        let mut chunk_stream = Chunks::new(stream, 3, Duration::new(10, 0))
+            .retry_inner(1)
+            .hold_on_error()
+            .wait();
+
+        // The items around the swallowed error share a batch.
+        assert_eq!(chunk_stream.next().unwrap().unwrap(), vec![1, 2, 3]);
+        // The second error in a row is not swallowed anymore.
+        assert!(chunk_stream.next().unwrap().unwrap_err().is_inner());
+        assert_eq!(chunk_stream.next().unwrap().unwrap(), vec![4]);
+        assert!(chunk_stream.next().is_none());
+    }
+
+    #[test]
+    fn message_yield_after() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut rt = tokio::runtime::current_thread::Runtime::new().unwrap();
+
+        for tag in 0..2 {
+            let log = log.clone();
+            let stream = stream::iter_ok::<_, io::Error>(0..1000);
+            let chunk_stream = Chunks::new(stream, 10, Duration::new(10, 0)).yield_after(1);
+            rt.spawn(
+                chunk_stream
+                    .for_each(move |_| {
+                        log.lock().unwrap().push(tag);
+                        Ok(())
+                    })
+                    .map_err(|e| panic!("{:?}", e)),
+            );
+        }
+        rt.run().unwrap();
+
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 200);
+        assert_eq!(log[..4], [0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn message_set_mode() {
+        let late = Delay::new(Instant::now() + Duration::from_millis(150))
+            .then(|_| Ok::<_, io::Error>(stream::iter_ok(vec![3, 4])))
+            .flatten_stream();
+        let stream = stream::iter_ok::<_, io::Error>(vec![1, 2]).chain(late);
+
+        let mut chunk_stream = Chunks::new(stream, 4, Duration::from_millis(50));
+
+        let v = tokio::runtime::current_thread::block_on_all(future::lazy(move || {
+            assert!(chunk_stream.poll().unwrap().is_not_ready());
+            assert!(chunk_stream.next_flush_will_be_timeout());
+
+            chunk_stream.set_mode(FlushMode::CapacityOnly);
+            assert!(chunk_stream.time_until_flush().is_none());
+            chunk_stream.collect()
+        }))
+        .unwrap();
+        assert_eq!(v, vec![vec![1, 2, 3, 4]]);
+    }
+
+    #[test]
+    fn message_new_with_rng() {
+        let mut offsets = vec![3_000_000_000, 250_000_000].into_iter();
+        let rng = move || offsets.next().unwrap();
+
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        let mut chunk_stream =
+            Chunks::new_with_rng(rx, 2, Duration::from_secs(10), Duration::from_secs(1), rng);
+
+        tokio::runtime::current_thread::block_on_all(future::lazy(move || {
+            let deadline =
+                |chunk_stream: &Chunks<_>| chunk_stream.clock.as_ref().unwrap().deadline();
+
+            // 3s is wrapped into the jitter range of 1s.
+            let before = Instant::now();
+            tx.unbounded_send(1).unwrap();
+            assert!(chunk_stream.poll().unwrap().is_not_ready());
+            let offset = Duration::new(10, 999_999_998);
+            assert!(deadline(&chunk_stream) >= before + offset);
+            assert!(deadline(&chunk_stream) <= Instant::now() + offset);
+
+            tx.unbounded_send(2).unwrap();
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(Some(vec![1, 2])));
+
+            let before = Instant::now();
+            tx.unbounded_send(3).unwrap();
+            assert!(chunk_stream.poll().unwrap().is_not_ready());
+            let offset = Duration::from_millis(10_250);
+            assert!(deadline(&chunk_stream) >= before + offset);
+            assert!(deadline(&chunk_stream) <= Instant::now() + offset);
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn message_complete() {
+        let stream = stream::iter_ok::<_, io::Error>(vec![0, 1, 2])
+            .chain(stream::poll_fn(|| Ok(Async::NotReady)));
+
+        let mut chunk_stream = Chunks::new(stream, 5, Duration::new(10, 0));
+
+        let v = tokio::runtime::current_thread::block_on_all(future::lazy(move || {
+            assert!(chunk_stream.poll().unwrap().is_not_ready());
+            chunk_stream.complete();
+            chunk_stream.collect()
+        }))
+        .unwrap();
+        assert_eq!(v, vec![vec![0, 1, 2]]);
+    }
+
+    #[test]
+    fn message_shared() {
+        let stream = stream::iter_ok::<_, io::Error>(vec![String::from("a"), String::from("b")]);
+
+        let chunk_stream = Chunks::new(stream, 5, Duration::new(10, 0)).shared();
+
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        assert_eq!(v.len(), 1);
+        assert_eq!(&v[0][..], ["a", "b"]);
+
+        let copy = v[0].clone();
+        assert!(Arc::ptr_eq(&copy, &v[0]));
+        assert_eq!(copy[0].as_ptr(), v[0][0].as_ptr());
+    }
+
+    #[test]
+    fn message_label() {
+        let stream = stream::iter_ok::<_, io::Error>(vec![1, 2, 3]);
+
+        let chunk_stream = Chunks::new(stream, 2, Duration::new(10, 0)).label("ingest");
+
+        let debug = format!("{:?}", chunk_stream);
+        assert!(
+            debug.starts_with("Chunks { label: Some(\"ingest\"), capacity: 2"),
+            "{}",
+            debug
+        );
+
+        let stream = stream::iter_result(vec![Ok(1), Err(io::Error::other("broken pipe"))]);
+        let chunk_stream = Chunks::new(stream, 2, Duration::new(10, 0)).label("ingest");
+        let err = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap_err();
+        assert_eq!(err.label(), Some("ingest"));
+        assert_eq!(err.to_string(), "ingest: broken pipe");
+
+        let err = Error::<io::Error>::new(Kind::Timer(timer::Error::shutdown()));
+        assert_eq!(err.label(), None);
+        assert_eq!(
+            err.to_string(),
+            format!("timer error: {}", timer::Error::shutdown())
+        );
+    }
+
+    #[test]
+    fn message_as_ref() {
+        fn skip<S: Stream>(s: &mut impl AsMut<S>) {
+            s.as_mut().poll().ok().unwrap();
+        }
+
+        let stream = stream::iter_ok::<_, io::Error>(vec![1, 2, 3]);
+        let mut chunk_stream = Chunks::new(stream, 2, Duration::new(10, 0));
+
+        let inner: &stream::IterOk<_, _> = chunk_stream.as_ref();
+        assert!(std::ptr::eq(inner, chunk_stream.get_ref()));
+
+        // Items pulled through `as_mut` never reach a batch.
+        skip(&mut chunk_stream);
+        let v: Vec<_> = chunk_stream.wait().map(Result::unwrap).collect();
+        assert_eq!(v, vec![vec![2, 3]]);
+    }
+
+    #[test]
+    fn message_into_remaining() {
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        for i in 0..5 {
+            tx.unbounded_send(i).unwrap();
+        }
+        let mut chunk_stream = Chunks::new(rx, 3, Duration::new(10, 0));
+
+        let (rx, remaining) =
+            tokio::runtime::current_thread::block_on_all(future::lazy(move || {
+                assert_eq!(
+                    chunk_stream.poll().unwrap(),
+                    Async::Ready(Some(vec![0, 1, 2]))
+                );
+                assert!(chunk_stream.poll().unwrap().is_not_ready());
+                Ok::<_, ()>(chunk_stream.into_remaining())
+            }))
+            .unwrap();
+
+        // Cancelling mid-batch hands back the buffered items instead of
+        // dropping them, and the stream can be picked up again.
+        assert_eq!(remaining, vec![3, 4]);
+        tx.unbounded_send(5).unwrap();
+        drop(tx);
+        let v = tokio::runtime::current_thread::block_on_all(rx.collect()).unwrap();
+        assert_eq!(v, vec![5]);
+    }
+
+    #[test]
+    fn message_into_remaining_while_validating() {
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        for i in 0..5 {
+            tx.unbounded_send(i).unwrap();
+        }
+        drop(tx);
+        let mut chunk_stream = Chunks::new(rx, 3, Duration::new(10, 0))
+            .validate(|_| future::empty::<bool, io::Error>(), OnReject::Drop);
+
+        let (rx, remaining) =
+            tokio::runtime::current_thread::block_on_all(future::lazy(move || {
+                assert!(chunk_stream.poll().unwrap().is_not_ready());
+                Ok::<_, ()>(chunk_stream.into_remaining())
+            }))
+            .unwrap();
+
+        // The batch waiting for its validation is handed back as well.
+        assert_eq!(remaining, vec![0, 1, 2]);
+        let v = tokio::runtime::current_thread::block_on_all(rx.collect()).unwrap();
+        assert_eq!(v, vec![3, 4]);
+    }
+
+    #[test]
+    fn message_counters_saturate() {
+        let stream = stream::iter_ok::<_, io::Error>(vec![1, 2, 3]);
+        let mut chunk_stream = Chunks::new(stream, 2, Duration::new(10, 0));
+        chunk_stream.counters.items = u64::MAX - 1;
+        chunk_stream.counters.batches = u64::MAX;
+
+        let mut chunk_stream = chunk_stream.wait();
+        assert_eq!(chunk_stream.next().unwrap().unwrap(), vec![1, 2]);
+        assert_eq!(chunk_stream.next().unwrap().unwrap(), vec![3]);
+        assert!(chunk_stream.next().is_none());
+
+        let counters = chunk_stream.get_ref().counters();
+        assert_eq!(counters.items, u64::MAX);
+        assert_eq!(counters.batches, u64::MAX);
+        assert_eq!(counters.capacity_flushes, 1);
+    }
+
+    #[test]
+    fn message_first_deadline() {
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        tx.unbounded_send(1).unwrap();
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(50);
+        let mut chunk_stream = Chunks::new(rx, 10, Duration::new(10, 0)).first_deadline(deadline);
+
+        let mut rt = tokio::runtime::current_thread::Runtime::new().unwrap();
+        let batch = rt
+            .block_on(future::poll_fn(|| chunk_stream.poll()))
+            .unwrap();
+        assert_eq!(batch, Some(vec![1]));
+        assert!(Instant::now() >= deadline);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        tx.unbounded_send(2).unwrap();
+        let left = rt
+            .block_on(future::lazy(|| {
+                assert!(chunk_stream.poll().unwrap().is_not_ready());
+                Ok::<_, ()>(chunk_stream.time_until_flush())
+            }))
+            .unwrap();
+        assert!(left.unwrap() > Duration::from_secs(5));
+    }
+
+    #[test]
+    fn message_unwrap_inner() {
+        let stream = stream::iter_result(vec![Ok(1), Err(io::Error::other("broken source"))]);
+        let chunk_stream = Chunks::new(stream, 2, Duration::new(10, 0));
+
+        let e = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap_err();
+        assert_eq!(e.unwrap_inner().to_string(), "broken source");
+    }
+
+    #[test]
+    #[should_panic(expected = "called `unwrap_inner` on a timer error")]
+    fn message_unwrap_inner_timer() {
+        Error::<io::Error>::new(Kind::Timer(timer::Error::shutdown())).unwrap_inner();
+    }
+
+    /// Collects the batches of `chunk_stream` together with the reason they
+    /// were flushed for.
+    fn with_reasons<S>(
+        mut chunk_stream: Chunks<S>,
+    ) -> impl Future<Item = Vec<(Vec<S::Item>, FlushReason)>, Error = Error<S::Error>>
+    where
+        S: Stream,
+    {
+        stream::poll_fn(move || {
+            let batch = try_ready!(chunk_stream.poll());
+            Ok(Async::Ready(batch.map(|batch| {
+                (batch, chunk_stream.last_flush_reason().unwrap())
+            })))
+        })
+        .collect()
+    }
+
+    #[test]
+    fn message_count_and_time_triggers() {
+        let late = Delay::new(Instant::now() + Duration::from_millis(100))
+            .then(|_| Ok::<_, io::Error>(stream::iter_ok(vec![4])))
+            .flatten_stream();
+        let stream = stream::iter_ok::<_, io::Error>(vec![1, 2, 3]).chain(late);
+
+        let chunk_stream = Chunks::new(stream, 2, Duration::from_millis(30));
+
+        let v = tokio::runtime::current_thread::block_on_all(with_reasons(chunk_stream)).unwrap();
+        assert_eq!(
+            v,
+            vec![
+                (vec![1, 2], FlushReason::Capacity),
+                (vec![3], FlushReason::Timeout),
+                (vec![4], FlushReason::End),
+            ]
+        );
+    }
+
+    #[test]
+    fn message_weight_and_boundary_triggers() {
+        let stream = stream::iter_ok::<_, io::Error>(vec![4, 4, 3, 7, 1, 7]);
+
+        let chunk_stream = Chunks::weighted(stream, 10, Duration::new(10, 0), |&n| n)
+            .flush_on(7, Sentinel::Include);
+
+        // The second batch reaches the maximum weight with a boundary item,
+        // and the weight is checked first.
+        let v = tokio::runtime::current_thread::block_on_all(with_reasons(chunk_stream)).unwrap();
+        assert_eq!(
+            v,
+            vec![
+                (vec![4, 4], FlushReason::Weight),
+                (vec![3, 7], FlushReason::Weight),
+                (vec![1, 7], FlushReason::Boundary),
+            ]
+        );
+    }
+
+    #[test]
+    fn message_poll_batch_info() {
+        let late = Delay::new(Instant::now() + Duration::from_millis(100))
+            .then(|_| Ok::<_, io::Error>(stream::iter_ok(vec![3])))
+            .flatten_stream();
+        let stream = stream::iter_ok::<_, io::Error>(vec![1, 2]).chain(late);
+        let mut chunk_stream = Chunks::new(stream, 5, Duration::from_millis(30));
+
+        let infos = tokio::runtime::current_thread::block_on_all(
+            stream::poll_fn(move || chunk_stream.poll_batch_info()).collect(),
+        )
+        .unwrap();
+
+        assert_eq!(infos.len(), 2);
+        assert_eq!(infos[0].items, vec![1, 2]);
+        assert_eq!(infos[0].reason, FlushReason::Timeout);
+        assert!(infos[0].age >= Duration::from_millis(30));
+        assert_eq!(infos[1].items, vec![3]);
+        assert_eq!(infos[1].reason, FlushReason::End);
+        assert!(infos[1].age < Duration::from_millis(30));
+    }
+
+    #[test]
+    fn message_poll_batch_info_absorb_small_tail() {
+        let late = Delay::new(Instant::now() + Duration::from_millis(100))
+            .then(|_| Ok::<_, io::Error>(stream::iter_ok(vec![3])))
+            .flatten_stream();
+        let stream = stream::iter_ok::<_, io::Error>(vec![1, 2]).chain(late);
+        let mut chunk_stream =
+            Chunks::new(stream, 5, Duration::from_millis(30)).absorb_small_tail(1);
+
+        let infos = tokio::runtime::current_thread::block_on_all(
+            stream::poll_fn(move || chunk_stream.poll_batch_info()).collect(),
+        )
+        .unwrap();
+
+        // The first batch is held back until the second one is flushed, but
+        // still reported with its own metadata.
+        assert_eq!(infos.len(), 2);
+        assert_eq!(infos[0].items, vec![1, 2]);
+        assert_eq!(infos[0].reason, FlushReason::Timeout);
+        assert!(infos[0].age >= Duration::from_millis(30));
+        assert_eq!(infos[1].items, vec![3]);
+        assert_eq!(infos[1].reason, FlushReason::End);
+        assert!(infos[1].age < Duration::from_millis(30));
+    }
+
+    #[test]
+    fn message_group_runs() {
+        let stream = stream::iter_ok::<_, io::Error>(vec!["a1", "a2", "b1", "b2", "b3", "a3"]);
+
+        let chunk_stream = Chunks::new(stream, 10, Duration::new(10, 0)).group_runs(|s| &s[..1]);
+
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        assert_eq!(
+            v,
+            vec![vec!["a1", "a2"], vec!["b1", "b2", "b3"], vec!["a3"]]
+        );
+
+        // The capacity still splits a long run.
+        let stream = stream::iter_ok::<_, io::Error>(vec![1, 1, 1, 2]);
+        let chunk_stream = Chunks::new(stream, 2, Duration::new(10, 0)).group_runs(|&n| n);
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        assert_eq!(v, vec![vec![1, 1], vec![1], vec![2]]);
+    }
+
+    #[test]
+    fn message_capacity_schedule() {
+        let stream = stream::iter_ok::<_, io::Error>(0..12);
+
+        let chunk_stream = Chunks::new(stream, 100, Duration::new(10, 0))
+            .capacity_schedule(|batch| [1, 2, 3][(batch % 3) as usize]);
+
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        let sizes: Vec<_> = v.iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![1, 2, 3, 1, 2, 3]);
+        assert_eq!(v.concat(), (0..12).collect::<Vec<_>>());
     }
 
     #[test]
-    fn message_early_exit() {
-        let iter = vec![1, 2, 3, 4].into_iter();
-        let stream = stream::iter_ok::<_, io::Error>(iter);
+    #[should_panic(expected = "capacity schedule returned 0 for batch 0")]
+    fn zero_first_scheduled_capacity_panics() {
+        let stream = stream::iter_ok::<_, io::Error>(0..4);
+        let _ = Chunks::new(stream, 2, Duration::new(10, 0)).capacity_schedule(|_| 0);
+    }
 
-        let chunk_stream = Chunks::new(stream, 5, Duration::new(100, 0));
+    #[test]
+    #[should_panic(expected = "capacity schedule returned 0 for batch 1")]
+    fn zero_later_scheduled_capacity_panics_on_poll() {
+        let stream = stream::iter_ok::<_, io::Error>(0..4);
 
-        let v = chunk_stream.collect();
-        tokio::run(v.then(|res| {
-            match res {
-                Err(_) => assert!(false),
-                Ok(v) => assert_eq!(vec![vec![1, 2, 3, 4]], v),
-            };
-            Ok(())
-        }));
+        let chunk_stream = Chunks::new(stream, 2, Duration::new(10, 0))
+            .capacity_schedule(|batch| if batch == 0 { 2 } else { 0 });
+
+        let _ = tokio::runtime::current_thread::block_on_all(chunk_stream.collect());
     }
 
     #[test]
-    fn message_timeout() {
-        let iter = vec![1, 2, 3, 4].into_iter();
-        let stream0 = stream::iter_ok::<_, io::Error>(iter);
+    fn message_pending_error() {
+        let stream = stream::iter_result(vec![
+            Ok(1),
+            Err(io::Error::other("first")),
+            Ok(2),
+            Err(io::Error::other("second")),
+            Ok(3),
+        ]);
+        let mut chunk_stream = Chunks::new(stream, 5, Duration::new(10, 0));
 
-        let iter = vec![5].into_iter();
-        let stream1 = stream::iter_ok::<_, io::Error>(iter).and_then(|n| {
-            Delay::new(Instant::now() + Duration::from_millis(300))
-                .and_then(move |_| Ok(n))
-                .map_err(|e| io::Error::new(io::ErrorKind::TimedOut, e))
-        });
+        tokio::runtime::current_thread::block_on_all(future::lazy(move || {
+            assert!(chunk_stream.pending_error().is_none());
 
-        let iter = vec![6, 7, 8].into_iter();
-        let stream2 = stream::iter_ok::<_, io::Error>(iter);
+            // The buffered item is returned before the error.
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(Some(vec![1])));
+            assert!(chunk_stream.pending_error().unwrap().is_inner());
+            assert!(chunk_stream.poll().is_err());
+            assert!(chunk_stream.pending_error().is_none());
 
-        let stream = stream0.chain(stream1).chain(stream2);
-        let chunk_stream = Chunks::new(stream, 5, Duration::from_millis(100));
+            // A taken error is not returned anymore.
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(Some(vec![2])));
+            let e = chunk_stream.take_pending_error().unwrap();
+            assert_eq!(e.unwrap_inner().to_string(), "second");
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(Some(vec![3])));
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn message_state_transitions() {
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        let mut chunk_stream = Chunks::new(rx, 2, Duration::new(10, 0));
+        assert_eq!(chunk_stream.state(), State::Idle);
+
+        tokio::runtime::current_thread::block_on_all(future::lazy(move || {
+            tx.unbounded_send(1).unwrap();
+            assert!(chunk_stream.poll().unwrap().is_not_ready());
+            assert_eq!(chunk_stream.state(), State::Accumulating);
+
+            tx.unbounded_send(2).unwrap();
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(Some(vec![1, 2])));
+            assert_eq!(chunk_stream.state(), State::Idle);
+
+            drop(tx);
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(None));
+            assert_eq!(chunk_stream.state(), State::Done);
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+
+        let stream = stream::iter_result(vec![Ok(1), Err(io::Error::other("broken"))]);
+        let mut chunk_stream = Chunks::new(stream, 2, Duration::new(10, 0));
+        tokio::runtime::current_thread::block_on_all(future::lazy(move || {
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(Some(vec![1])));
+            assert_eq!(chunk_stream.state(), State::PendingError);
+            assert!(chunk_stream.poll().is_err());
+            assert_eq!(chunk_stream.state(), State::Idle);
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn message_max_buffer_bytes() {
+        let stream = stream::iter_ok::<_, io::Error>(Vec::<u64>::new());
+        let chunk_stream = Chunks::new(stream, 10, Duration::new(10, 0));
+        assert_eq!(chunk_stream.max_buffer_bytes(), 80);
+
+        let stream = stream::iter_ok::<_, io::Error>(Vec::<String>::new());
+        let chunk_stream = Chunks::weighted(stream, 4096, Duration::new(10, 0), String::len);
+        assert_eq!(chunk_stream.max_buffer_bytes(), 4096);
+    }
+
+    #[test]
+    fn message_validate() {
+        let items = || stream::iter_ok::<_, io::Error>(1..8);
+        let run = |chunk_stream: Chunks<_>| {
+            tokio::runtime::current_thread::block_on_all(chunk_stream.collect())
+        };
+
+        let chunk_stream = Chunks::new(items(), 3, Duration::new(10, 0))
+            .validate(|_| future::ok::<_, io::Error>(true), OnReject::Drop);
+        assert_eq!(
+            run(chunk_stream).unwrap(),
+            vec![vec![1, 2, 3], vec![4, 5, 6], vec![7]]
+        );
+
+        let chunk_stream = Chunks::new(items(), 3, Duration::new(10, 0)).validate(
+            |batch| future::ok::<_, io::Error>(!batch.contains(&5)),
+            OnReject::Drop,
+        );
+        assert_eq!(run(chunk_stream).unwrap(), vec![vec![1, 2, 3], vec![7]]);
+
+        // Batches with an odd sum are merged into the next batch.
+        let chunk_stream = Chunks::new(items(), 2, Duration::new(10, 0)).validate(
+            |batch| future::ok::<_, io::Error>(batch.iter().sum::<u32>() % 2 == 0),
+            OnReject::Rebuffer,
+        );
+        assert_eq!(
+            run(chunk_stream).unwrap(),
+            vec![vec![1, 2, 3], vec![4, 5, 6, 7]]
+        );
+
+        let chunk_stream = Chunks::new(items(), 3, Duration::new(10, 0)).validate(
+            |batch| {
+                if batch.contains(&4) {
+                    future::err("validation service down")
+                } else {
+                    future::ok(true)
+                }
+            },
+            OnReject::Drop,
+        );
+        let e = run(chunk_stream).unwrap_err();
+        assert!(e.is_validation());
+        assert_eq!(
+            e.into_validation().unwrap().to_string(),
+            "validation service down"
+        );
+    }
+
+    #[test]
+    fn message_validate_rebuffer_timestamped() {
+        let stream = stream::iter_ok::<_, io::Error>(1..7);
+        // A rejected batch still takes up its memory once buffered again, so
+        // the next item flushes it.
+        let chunk_stream = Chunks::new(stream, 10, Duration::new(10, 0))
+            .max_memory(2, |_| 1)
+            .validate(
+                |batch| future::ok::<_, io::Error>(batch.len() > 2),
+                OnReject::Rebuffer,
+            )
+            .timestamped();
+
+        let batches = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        let items: Vec<_> = batches.iter().map(|batch| batch.items.clone()).collect();
+        assert_eq!(items, vec![vec![1, 2, 3], vec![4, 5, 6]]);
+        assert!(batches
+            .iter()
+            .all(|batch| batch.first_item_at <= batch.emitted_at));
+    }
+
+    #[test]
+    fn message_flush_handle() {
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        let mut chunk_stream = Chunks::new(rx, 10, Duration::new(10, 0));
+        let handle = chunk_stream.flush_handle();
+
+        tokio::runtime::current_thread::block_on_all(future::lazy(move || {
+            // Nothing is flushed while nothing is buffered.
+            handle.flush();
+            assert!(chunk_stream.poll().unwrap().is_not_ready());
+
+            tx.unbounded_send(1).unwrap();
+            tx.unbounded_send(2).unwrap();
+            assert!(chunk_stream.poll().unwrap().is_not_ready());
+
+            handle.flush();
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(Some(vec![1, 2])));
+            assert_eq!(chunk_stream.last_flush_reason(), Some(FlushReason::Signal));
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn message_flush_on_pressure() {
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut chunk_stream =
+            Chunks::new(rx, 10, Duration::new(10, 0)).flush_on_pressure(flag.clone());
+
+        tokio::runtime::current_thread::block_on_all(future::lazy(move || {
+            // The flag is cleared even if there is nothing to shed.
+            flag.store(true, Ordering::SeqCst);
+            assert!(chunk_stream.poll().unwrap().is_not_ready());
+            assert!(!flag.load(Ordering::SeqCst));
+
+            tx.unbounded_send(1).unwrap();
+            tx.unbounded_send(2).unwrap();
+            assert!(chunk_stream.poll().unwrap().is_not_ready());
+
+            flag.store(true, Ordering::SeqCst);
+            tx.unbounded_send(3).unwrap();
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(Some(vec![1, 2])));
+            assert_eq!(
+                chunk_stream.last_flush_reason(),
+                Some(FlushReason::Pressure)
+            );
+            assert!(!flag.load(Ordering::SeqCst));
+
+            drop(tx);
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(Some(vec![3])));
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn message_batch_backends() {
+        use std::collections::VecDeque;
+
+        let items = || {
+            let late = Delay::new(Instant::now() + Duration::from_millis(100))
+                .then(|_| Ok::<_, io::Error>(stream::iter_ok(vec![6, 7])))
+                .flatten_stream();
+            stream::iter_ok::<_, io::Error>(vec![0, 1, 2, 3, 4, 5]).chain(late)
+        };
+
+        let vec_stream = Chunks::new(items(), 4, Duration::from_millis(50));
+        let deque_stream =
+            Chunks::<_, VecDeque<_>>::with_batch(items(), 4, Duration::from_millis(50));
+
+        let (vecs, deques) = tokio::runtime::current_thread::block_on_all(
+            vec_stream.collect().join(deque_stream.collect()),
+        )
+        .unwrap();
+        assert_eq!(vecs, vec![vec![0, 1, 2, 3], vec![4, 5], vec![6, 7]]);
+        let deques: Vec<Vec<_>> = deques.into_iter().map(Vec::from).collect();
+        assert_eq!(deques, vecs);
+    }
+
+    #[test]
+    fn message_soft_flush_at() {
+        let stream =
+            stream::iter_ok::<_, io::Error>(0..25).chain(stream::poll_fn(|| Ok(Async::NotReady)));
+
+        let chunk_stream = Chunks::new(stream, 100, Duration::new(10, 0)).soft_flush_at(20);
 
         let now = Instant::now();
-        let min_times = [Duration::from_millis(80), Duration::from_millis(150)];
-        let max_times = [Duration::from_millis(280), Duration::from_millis(350)];
-        let results = vec![vec![1, 2, 3, 4], vec![5, 6, 7, 8]];
-        let mut i = 0;
+        let (v, _) = tokio::runtime::current_thread::block_on_all(chunk_stream.into_future())
+            .map_err(|(e, _)| e)
+            .unwrap();
+        assert!(Instant::now() - now < Duration::from_secs(1));
+        assert_eq!(v, Some((0..25).collect()));
+    }
 
-        let v = chunk_stream
-            .map(move |s| {
-                let now2 = Instant::now();
-                println!("{:?}", now2 - now);
-                assert!((now2 - now) < max_times[i]);
-                assert!((now2 - now) > min_times[i]);
-                i += 1;
-                s
+    #[test]
+    fn message_capacity_one() {
+        let armed = Arc::new(Mutex::new(0));
+        let armed2 = armed.clone();
+
+        let stream = stream::iter_ok::<_, io::Error>(vec!['a', 'b', 'c']);
+        let mut chunk_stream =
+            Chunks::new(stream, 1, Duration::new(10, 0)).item_timeout(move |_| {
+                *armed2.lock().unwrap() += 1;
+                Duration::new(10, 0)
+            });
+
+        let v = tokio::runtime::current_thread::block_on_all(future::lazy(move || {
+            let mut v = Vec::new();
+            while let Async::Ready(Some(batch)) = chunk_stream.poll()? {
+                assert!(!chunk_stream.is_timer_armed());
+                v.push(batch);
+            }
+            Ok::<_, Error<io::Error>>(v)
+        }))
+        .unwrap();
+        assert_eq!(v, vec![vec!['a'], vec!['b'], vec!['c']]);
+        assert_eq!(*armed.lock().unwrap(), 0);
+    }
+
+    /// Polls `chunk_stream` on a mock clock, calling `step` with the offset of
+    /// the clock from its start to move the clock and feed the stream.
+    fn drive_mock_clock<S, F>(chunk_stream: Chunks<S>, mut step: F)
+    where
+        S: Stream,
+        S::Error: fmt::Debug,
+        F: FnMut(&mut Duration, &mut Chunks<S>, Option<Vec<S::Item>>) -> bool,
+    {
+        use futures::executor::{self, Notify, NotifyHandle};
+        use tokio_executor::park::ParkThread;
+        use tokio_timer::clock::{Clock, Now};
+        use tokio_timer::timer::{self, Timer};
+
+        #[derive(Clone)]
+        struct MockNow(Instant, Arc<Mutex<Duration>>);
+        impl Now for MockNow {
+            fn now(&self) -> Instant {
+                self.0 + *self.1.lock().unwrap()
+            }
+        }
+        struct NoopNotify;
+        impl Notify for NoopNotify {
+            fn notify(&self, _: usize) {}
+        }
+
+        let offset = Arc::new(Mutex::new(Duration::new(0, 0)));
+        let mock = Clock::new_with_now(MockNow(Instant::now(), offset.clone()));
+        let mut timer = Timer::new_with_now(ParkThread::new(), mock.clone());
+        let handle = timer.handle();
+        let notify = NotifyHandle::from(Arc::new(NoopNotify));
+        let mut enter = tokio_executor::enter().unwrap();
+        let mut chunk_stream = executor::spawn(chunk_stream);
+
+        tokio_timer::clock::with_default(&mock, &mut enter, |enter| {
+            timer::with_default(&handle, enter, |_| {
+                let mut batch = None;
+                let mut now = Duration::new(0, 0);
+                while step(&mut now, chunk_stream.get_mut(), batch.take()) {
+                    *offset.lock().unwrap() = now;
+                    timer.turn(Some(Duration::new(0, 0))).unwrap();
+                    if let Async::Ready(b) = chunk_stream.poll_stream_notify(&notify, 0).unwrap() {
+                        batch = b;
+                    }
+                }
             })
-            .collect();
+        });
+    }
 
-        tokio::run(v.then(move |res| {
-            match res {
-                Err(_) => assert!(false),
-                Ok(v) => assert_eq!(v, results),
-            };
-            Ok(())
-        }));
+    #[test]
+    fn message_interval_ends_without_empty_batch() {
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        let mut chunk_stream = Chunks::new(rx, 10, Duration::from_millis(10));
+        chunk_stream.set_mode(FlushMode::Interval);
+
+        let mut tx = Some(tx);
+        let mut steps = 0;
+        let mut batches = Vec::new();
+        drive_mock_clock(chunk_stream, |now, chunk_stream, batch| {
+            batches.extend(batch);
+            steps += 1;
+            match steps {
+                1 => tx.as_ref().unwrap().unbounded_send(1).unwrap(),
+                // The tick flushes the batch, and the stream ends right after.
+                2 => *now += Duration::from_millis(10),
+                3 => drop(tx.take()),
+                _ => {
+                    assert!(chunk_stream.snapshot().is_empty());
+                    *now += Duration::from_millis(10);
+                }
+            }
+            steps < 6
+        });
+
+        assert_eq!(batches, vec![vec![1]]);
+    }
+
+    #[test]
+    fn message_interval_without_drift() {
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        let mut chunk_stream = Chunks::new(rx, 10, Duration::from_millis(10));
+        chunk_stream.set_mode(FlushMode::Interval);
+
+        let mut deadlines = Vec::new();
+        let mut batches = 0;
+        drive_mock_clock(chunk_stream, |now, chunk_stream, batch| {
+            if batch.is_some() {
+                batches += 1;
+            }
+            if let Some(left) = chunk_stream.time_until_flush() {
+                let deadline = *now + left;
+                if deadlines.last() != Some(&deadline) {
+                    deadlines.push(deadline);
+                }
+            }
+            *now += Duration::from_millis(3);
+            tx.unbounded_send(0).unwrap();
+            *now < Duration::from_secs(1)
+        });
+
+        // Arriving 3ms after the previous batch would delay every batch by
+        // up to 3ms, if the timer was re-armed relative to the arrival.
+        assert_eq!(batches, 99);
+        for (k, deadline) in deadlines.into_iter().enumerate() {
+            assert_eq!(deadline, Duration::from_millis(13 + 10 * k as u64));
+        }
+    }
+
+    #[test]
+    fn message_timer_on_capacity_flush() {
+        for &(policy, deadline) in &[(TimerPolicy::Reset, 14), (TimerPolicy::Keep, 10)] {
+            let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+            let chunk_stream =
+                Chunks::new(rx, 2, Duration::from_millis(10)).timer_on_capacity_flush(policy);
+
+            let mut steps = 0;
+            let mut batches = Vec::new();
+            drive_mock_clock(chunk_stream, |now, chunk_stream, batch| {
+                batches.extend(batch);
+                steps += 1;
+                match steps {
+                    1 => tx.unbounded_send(1).unwrap(),
+                    2 => {
+                        *now += Duration::from_millis(3);
+                        tx.unbounded_send(2).unwrap();
+                    }
+                    3 => {
+                        *now += Duration::from_millis(1);
+                        tx.unbounded_send(3).unwrap();
+                    }
+                    4 => {
+                        let left = chunk_stream.time_until_flush().unwrap();
+                        assert_eq!(*now + left, Duration::from_millis(deadline));
+                        *now += left;
+                    }
+                    _ => return false,
+                }
+                true
+            });
+            assert_eq!(batches, vec![vec![1, 2], vec![3]], "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn message_min_latency() {
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        let chunk_stream = Chunks::new(rx, 100, Duration::new(10, 0))
+            .eager_flush()
+            .min_latency(Duration::from_millis(10));
+
+        let mut steps = 0;
+        let mut batches = Vec::new();
+        drive_mock_clock(chunk_stream, |now, _, batch| {
+            if let Some(batch) = batch {
+                batches.push((*now, batch));
+            }
+            steps += 1;
+            match steps {
+                1 => tx.unbounded_send(1).unwrap(),
+                // The stream blinks idle between these items, but the last
+                // batch was emitted too recently.
+                2..=4 => {
+                    *now += Duration::from_millis(3);
+                    tx.unbounded_send(steps).unwrap();
+                }
+                5 => *now = Duration::from_millis(10),
+                _ => return false,
+            }
+            true
+        });
+
+        assert_eq!(
+            batches,
+            vec![
+                (Duration::new(0, 0), vec![1]),
+                (Duration::from_millis(10), vec![2, 3, 4]),
+            ]
+        );
+    }
+
+    #[test]
+    fn message_timeouts() {
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        let chunk_stream = Chunks::new(rx, 100, Duration::new(10, 0))
+            .timeouts(&[Duration::from_millis(5), Duration::from_millis(20)]);
+
+        let mut steps = 0;
+        let mut deadlines = Vec::new();
+        let mut batches = 0;
+        drive_mock_clock(chunk_stream, |now, chunk_stream, batch| {
+            if batch.is_some() {
+                batches += 1;
+            }
+            steps += 1;
+            if steps % 2 == 1 {
+                tx.unbounded_send(steps).unwrap();
+            } else {
+                let left = chunk_stream.time_until_flush().unwrap();
+                deadlines.push(*now + left);
+                *now += left;
+            }
+            steps < 8
+        });
+
+        let ms = Duration::from_millis;
+        assert_eq!(deadlines, vec![ms(5), ms(25), ms(30), ms(50)]);
+        assert_eq!(batches, 3);
+    }
+
+    #[test]
+    fn message_flush_when() {
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        let chunk_stream = Chunks::new(rx, 100, Duration::new(10, 0))
+            .flush_when(|| Delay::new(clock::now() + Duration::from_millis(20)));
+
+        let mut sent = 0;
+        let mut flushed = Vec::new();
+        drive_mock_clock(chunk_stream, |now, chunk_stream, batch| {
+            if let Some(batch) = batch {
+                assert_eq!(chunk_stream.last_flush_reason(), Some(FlushReason::Custom));
+                flushed.push((*now, batch));
+            }
+            *now += Duration::from_millis(5);
+            tx.unbounded_send(sent).unwrap();
+            sent += 1;
+            *now < Duration::from_millis(100)
+        });
+
+        // Every future is created by the poll after the previous flush, when
+        // the next item arrives 5ms later.
+        let ms = Duration::from_millis;
+        assert_eq!(
+            flushed,
+            vec![
+                (ms(25), vec![0, 1, 2, 3, 4]),
+                (ms(50), vec![5, 6, 7, 8, 9]),
+                (ms(75), vec![10, 11, 12, 13, 14]),
+            ]
+        );
+    }
+
+    #[test]
+    fn message_flush_when_errors() {
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        let mut chunk_stream = Chunks::new(rx, 100, Duration::new(10, 0))
+            .flush_when(|| future::err::<(), _>("trigger broke"));
+
+        tokio::runtime::current_thread::block_on_all(future::lazy(move || {
+            tx.unbounded_send(1).unwrap();
+            assert_eq!(chunk_stream.poll().unwrap(), Async::Ready(Some(vec![1])));
+            let e = chunk_stream.poll().unwrap_err();
+            assert_eq!(e.into_trigger().unwrap().to_string(), "trigger broke");
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn message_reconfigure_and_flush() {
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        let chunk_stream = Chunks::new(rx, 10, Duration::from_millis(10));
+
+        let mut steps = 0;
+        let mut deadline = None;
+        drive_mock_clock(chunk_stream, |now, chunk_stream, _| {
+            steps += 1;
+            match steps {
+                1 => {
+                    tx.unbounded_send(1).unwrap();
+                    tx.unbounded_send(2).unwrap();
+                }
+                2 => {
+                    *now += Duration::from_millis(3);
+                    let batch = chunk_stream.reconfigure_and_flush(Duration::from_millis(20));
+                    assert_eq!(batch, Some(vec![1, 2]));
+                    assert_eq!(
+                        chunk_stream.last_flush_reason(),
+                        Some(FlushReason::Reconfigure)
+                    );
+                    assert!(!chunk_stream.is_timer_armed());
+                    tx.unbounded_send(3).unwrap();
+                }
+                _ => deadline = chunk_stream.time_until_flush().map(|left| *now + left),
+            }
+            steps < 3
+        });
+
+        // The batch after the change uses the new timeout.
+        assert_eq!(deadline, Some(Duration::from_millis(23)));
+    }
+
+    #[test]
+    #[cfg(feature = "metrics")]
+    fn message_arrival_gap_stats() {
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        let chunk_stream = Chunks::new(rx, 100, Duration::new(10, 0));
+        assert_eq!(chunk_stream.arrival_gap_stats(), GapStats::default());
+
+        let mut steps = 0;
+        let mut stats = GapStats::default();
+        drive_mock_clock(chunk_stream, |now, chunk_stream, _| {
+            // Items arrive at 0ms, 1ms, 3ms, 6ms and 10ms.
+            *now += Duration::from_millis(steps);
+            tx.unbounded_send(0).unwrap();
+            steps += 1;
+            stats = chunk_stream.arrival_gap_stats();
+            steps < 6
+        });
+
+        let ms = Duration::from_millis;
+        assert_eq!(stats.count, 4);
+        assert_eq!((stats.min, stats.max), (ms(1), ms(4)));
+        assert_eq!(stats.mean, Duration::from_micros(2500));
+        assert_eq!((stats.p50, stats.p99), (ms(2), ms(4)));
+    }
+
+    #[test]
+    fn message_unbounded_capacity() {
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        let chunk_stream = Chunks::new(rx, usize::MAX, Duration::from_millis(10));
+        assert!(chunk_stream.items.capacity() <= MAX_PREALLOCATE);
+
+        let mut steps = 0;
+        let mut batches = Vec::new();
+        drive_mock_clock(chunk_stream, |now, chunk_stream, batch| {
+            batches.extend(batch);
+            steps += 1;
+            match steps {
+                1 => (0..MAX_PREALLOCATE as u32 + 10).for_each(|i| tx.unbounded_send(i).unwrap()),
+                2 => {
+                    // The buffer grew past the initial allocation on demand.
+                    assert!(chunk_stream.items.capacity() > MAX_PREALLOCATE);
+                    *now += Duration::from_millis(10);
+                }
+                _ => assert!(chunk_stream.items.capacity() <= MAX_PREALLOCATE),
+            }
+            steps < 3
+        });
+
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].len(), MAX_PREALLOCATE + 10);
+    }
+
+    #[test]
+    fn message_ttl() {
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        let chunk_stream =
+            Chunks::new(rx, 100, Duration::from_secs(60)).ttl(Duration::from_millis(10));
+
+        let mut steps = 0;
+        let mut deadlines = Vec::new();
+        let mut batches = Vec::new();
+        drive_mock_clock(chunk_stream, |now, chunk_stream, batch| {
+            batches.extend(batch);
+            steps += 1;
+            if let Some(left) = chunk_stream.time_until_flush() {
+                deadlines.push(*now + left);
+            }
+            match steps {
+                1..=3 => tx.unbounded_send(steps).unwrap(),
+                4 => *now += Duration::from_millis(4),
+                5 => {
+                    tx.unbounded_send(steps).unwrap();
+                    *now += Duration::from_millis(4);
+                }
+                6 => {
+                    tx.unbounded_send(steps).unwrap();
+                    *now += Duration::from_millis(2);
+                }
+                7 => tx.unbounded_send(steps).unwrap(),
+                _ => {}
+            }
+            steps < 8
+        });
+
+        // The later items of a batch never push back the deadline set by its
+        // first item. The item pulled when it expired starts the next batch,
+        // with its own deadline.
+        let ms = Duration::from_millis;
+        assert_eq!(batches, vec![vec![1, 2, 3, 5]]);
+        assert_eq!(
+            deadlines,
+            vec![ms(10), ms(10), ms(10), ms(10), ms(10), ms(20)]
+        );
+    }
+
+    #[test]
+    fn message_with_capacity_and_interval() {
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        let chunk_stream = Chunks::with_capacity_and_interval(rx, 2, Duration::from_millis(10));
+
+        let mut steps = 0;
+        let mut batches = Vec::new();
+        drive_mock_clock(chunk_stream, |now, _, batch| {
+            batches.extend(batch);
+            steps += 1;
+            match steps {
+                1 => (1..4).for_each(|i| tx.unbounded_send(i).unwrap()),
+                3 | 4 => *now += Duration::from_millis(10),
+                5 => (4..6).for_each(|i| tx.unbounded_send(i).unwrap()),
+                6 | 7 => *now += Duration::from_millis(5),
+                _ => {}
+            }
+            steps < 8
+        });
+
+        // The ticks at 10ms, 20ms and 30ms flush whatever was buffered, also
+        // after the capacity flushed a batch in between.
+        assert_eq!(
+            batches,
+            vec![vec![1, 2], vec![3], vec![], vec![4, 5], vec![]]
+        );
+    }
+
+    #[test]
+    fn message_heartbeat_reason_and_padding() {
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        let chunk_stream =
+            Chunks::with_capacity_and_interval(rx, 3, Duration::from_millis(10)).pad_to_capacity();
+        let mut tx = Some(tx);
+
+        let mut steps = 0;
+        let mut batches = Vec::new();
+        let mut timeouts = None;
+        drive_mock_clock(chunk_stream, |now, chunk_stream, batch| {
+            if let Some(batch) = batch {
+                batches.push((batch, chunk_stream.last_flush_reason().unwrap()));
+            }
+            steps += 1;
+            match steps {
+                1 => (1..6).for_each(|i| tx.as_ref().unwrap().unbounded_send(i).unwrap()),
+                3 | 4 => *now += Duration::from_millis(10),
+                5 => tx.take().unwrap().unbounded_send(6).unwrap(),
+                _ => timeouts = Some(chunk_stream.counters().timeouts),
+            }
+            steps < 7
+        });
+
+        // The heartbeats are emitted as they are, unlike the final batch.
+        assert_eq!(
+            batches,
+            vec![
+                (vec![1, 2, 3], FlushReason::Capacity),
+                (vec![4, 5], FlushReason::Heartbeat),
+                (vec![], FlushReason::Heartbeat),
+                (vec![6, 0, 0], FlushReason::End),
+            ]
+        );
+        assert_eq!(timeouts, Some(0));
+    }
+
+    #[test]
+    fn message_avg_batch_interval() {
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        let chunk_stream = Chunks::new(rx, 1, Duration::from_secs(60));
+        assert_eq!(chunk_stream.avg_batch_interval(), None);
+
+        let mut steps = 0;
+        let mut stats = None;
+        drive_mock_clock(chunk_stream, |now, chunk_stream, _| {
+            steps += 1;
+            if steps <= 4 {
+                *now += Duration::from_millis(10);
+                tx.unbounded_send(steps).unwrap();
+            } else {
+                stats = Some((chunk_stream.uptime(), chunk_stream.avg_batch_interval()));
+            }
+            steps < 5
+        });
+
+        let (uptime, avg) = stats.unwrap();
+        let avg = avg.unwrap();
+        assert!(uptime >= Duration::from_millis(40), "{:?}", uptime);
+        assert!(avg >= Duration::from_millis(10), "{:?}", avg);
+        assert!(avg < Duration::from_millis(15), "{:?}", avg);
+    }
+
+    #[test]
+    fn message_catchup_timeout() {
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        let chunk_stream =
+            Chunks::new(rx, 2, Duration::from_millis(10)).catchup_timeout(Duration::from_millis(2));
+
+        let mut steps = 0;
+        let mut deadlines = Vec::new();
+        let mut batches = Vec::new();
+        drive_mock_clock(chunk_stream, |now, chunk_stream, batch| {
+            batches.extend(batch);
+            steps += 1;
+            match steps {
+                1 | 3 | 5 => tx.unbounded_send(steps).unwrap(),
+                2 => {
+                    deadlines.push(*now + chunk_stream.time_until_flush().unwrap());
+                    *now += Duration::from_millis(10);
+                }
+                4 => {
+                    deadlines.push(*now + chunk_stream.time_until_flush().unwrap());
+                    tx.unbounded_send(steps).unwrap();
+                }
+                _ => deadlines.push(*now + chunk_stream.time_until_flush().unwrap()),
+            }
+            steps < 6
+        });
+
+        // The batch after the timeout uses the catch-up timeout, and the one
+        // after the capacity flush the regular timeout again.
+        let ms = Duration::from_millis;
+        assert_eq!(batches, vec![vec![1], vec![3, 4]]);
+        assert_eq!(deadlines, vec![ms(10), ms(12), ms(20)]);
+    }
+
+    #[test]
+    fn message_high_priority_timeout() {
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        let chunk_stream = Chunks::new(rx, 10, Duration::from_millis(10))
+            .prioritize(|&i| {
+                if i % 2 == 0 {
+                    Priority::High
+                } else {
+                    Priority::Low
+                }
+            })
+            .high_priority_timeout(Duration::from_millis(2));
+
+        let mut steps = 0;
+        let mut deadlines = Vec::new();
+        let mut batches = Vec::new();
+        drive_mock_clock(chunk_stream, |now, chunk_stream, batch| {
+            batches.extend(batch);
+            steps += 1;
+            match steps {
+                1 => tx.unbounded_send(1).unwrap(),
+                2 => {
+                    deadlines.push(*now + chunk_stream.time_until_flush().unwrap());
+                    *now += Duration::from_millis(1);
+                    tx.unbounded_send(2).unwrap();
+                }
+                3 => {
+                    deadlines.push(*now + chunk_stream.time_until_flush().unwrap());
+                    *now += Duration::from_millis(2);
+                }
+                _ => {}
+            }
+            steps < 4
+        });
+
+        // The high priority item moves the deadline of its batch closer and
+        // is emitted first.
+        let ms = Duration::from_millis;
+        assert_eq!(batches, vec![vec![2, 1]]);
+        assert_eq!(deadlines, vec![ms(10), ms(3)]);
+    }
+
+    #[test]
+    fn message_bounded_latency() {
+        // An item arrives every half timeout, so the stream is never idle for
+        // long and the batch never fills up.
+        let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+        let chunk_stream = Chunks::new(rx, 100, Duration::from_millis(10));
+
+        let mut first = None;
+        let mut sent = 0;
+        drive_mock_clock(chunk_stream, |now, _, batch| {
+            if batch.is_some() && first.is_none() {
+                first = Some(*now);
+            }
+            *now += Duration::from_millis(5);
+            tx.unbounded_send(sent).unwrap();
+            sent += 1;
+            *now < Duration::from_millis(100)
+        });
+        // The first item arrives at 5ms.
+        assert_eq!(first, Some(Duration::from_millis(15)));
+
+        // A stream that is always ready but whose items are all filtered out
+        // still lets the timer flush the batch.
+        let stream = stream::repeat::<_, io::Error>(1);
+        let chunk_stream = Chunks::distinct(stream, 10, Duration::from_millis(20));
+
+        let start = Instant::now();
+        let (batch, _) = tokio::runtime::current_thread::block_on_all(chunk_stream.into_future())
+            .map_err(|(e, _)| e)
+            .unwrap();
+        assert_eq!(batch, Some(vec![1]));
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(20));
+        assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn message_interval_missed_ticks() {
+        for &(behavior, ref expected) in &[
+            (MissedTickBehavior::Burst, (Some(vec![1]), None)),
+            (
+                MissedTickBehavior::Skip,
+                (None, Some(Duration::from_millis(50))),
+            ),
+        ] {
+            let (tx, rx) = futures::sync::mpsc::unbounded::<u32>();
+            let mut chunk_stream =
+                Chunks::new(rx, 10, Duration::from_millis(10)).missed_tick_behavior(behavior);
+            chunk_stream.set_mode(FlushMode::Interval);
+
+            let mut steps = 0;
+            let mut result = None;
+            drive_mock_clock(chunk_stream, |now, chunk_stream, batch| {
+                steps += 1;
+                match steps {
+                    // The first tick is at 10ms.
+                    1 => tx.unbounded_send(0).unwrap(),
+                    // The task wakes up late and misses the tick at 20ms.
+                    2 => *now += Duration::from_millis(45),
+                    3 => tx.unbounded_send(1).unwrap(),
+                    _ => {
+                        let deadline = chunk_stream.time_until_flush().map(|left| *now + left);
+                        result = Some((batch, deadline));
+                        return false;
+                    }
+                }
+                true
+            });
+            assert_eq!(result.as_ref(), Some(expected), "{:?}", behavior);
+        }
     }
 }
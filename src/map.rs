@@ -0,0 +1,109 @@
+use futures::stream::Stream;
+use futures::{Async, Poll};
+
+use crate::{BoxError, Chunks, Error, Kind};
+
+/// An adaptor for `Chunks` that transforms every batch with a fallible
+/// function.
+///
+/// An error of the function is returned by the stream like the other errors
+/// of `Chunks`, see `Error::is_map`. The stream keeps going afterwards, so
+/// the batch that failed is lost but later batches are transformed as usual.
+///
+/// This is created by `Chunks::map_ok_batch`.
+#[must_use = "streams do nothing unless polled"]
+pub struct MapOkBatch<S, F>
+where
+    S: Stream,
+{
+    chunks: Chunks<S>,
+    f: F,
+}
+
+impl<S, F, U, E> MapOkBatch<S, F>
+where
+    S: Stream,
+    F: FnMut(Vec<S::Item>) -> Result<U, E>,
+    E: Into<BoxError>,
+{
+    pub(crate) fn new(chunks: Chunks<S>, f: F) -> MapOkBatch<S, F> {
+        MapOkBatch { chunks, f }
+    }
+
+    /// Acquires a reference to the underlying `Chunks`.
+    pub fn get_ref(&self) -> &Chunks<S> {
+        &self.chunks
+    }
+
+    /// Acquires a mutable reference to the underlying `Chunks`.
+    pub fn get_mut(&mut self) -> &mut Chunks<S> {
+        &mut self.chunks
+    }
+
+    /// Consumes this combinator, returning the underlying `Chunks`.
+    pub fn into_inner(self) -> Chunks<S> {
+        self.chunks
+    }
+}
+
+impl<S, F, U, E> Stream for MapOkBatch<S, F>
+where
+    S: Stream,
+    F: FnMut(Vec<S::Item>) -> Result<U, E>,
+    E: Into<BoxError>,
+{
+    type Item = U;
+    type Error = Error<S::Error>;
+
+    fn poll(&mut self) -> Poll<Option<U>, Self::Error> {
+        let batch = match self.chunks.poll()? {
+            Async::Ready(Some(batch)) => batch,
+            Async::Ready(None) => return Ok(Async::Ready(None)),
+            Async::NotReady => return Ok(Async::NotReady),
+        };
+        match (self.f)(batch) {
+            Ok(mapped) => Ok(Async::Ready(Some(mapped))),
+            Err(e) => Err(Error {
+                kind: Kind::Map(e.into()),
+                label: self.chunks.label,
+            }),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "timer"))]
+mod tests {
+    use super::*;
+    use futures::stream;
+    use std::io;
+    use std::time::Duration;
+
+    #[test]
+    fn map_batches() {
+        let stream = stream::iter_ok::<_, io::Error>(0..5);
+        let summed = Chunks::new(stream, 2, Duration::new(10, 0))
+            .map_ok_batch(|batch| Ok::<_, io::Error>(batch.iter().sum::<u32>()));
+
+        let v = tokio::runtime::current_thread::block_on_all(summed.collect()).unwrap();
+        assert_eq!(v, vec![1, 5, 4]);
+    }
+
+    #[test]
+    fn map_errors() {
+        let stream = stream::iter_ok::<_, io::Error>(vec!["1", "x", "3"]);
+        let mut parsed = Chunks::new(stream, 1, Duration::new(10, 0))
+            .map_ok_batch(|batch| batch[0].parse::<u32>())
+            .wait();
+
+        assert_eq!(parsed.next().unwrap().unwrap(), 1);
+        let e = parsed.next().unwrap().unwrap_err();
+        assert!(e.is_map());
+        assert!(!e.is_inner());
+        assert_eq!(
+            e.into_map().unwrap().to_string(),
+            "invalid digit found in string"
+        );
+        assert_eq!(parsed.next().unwrap().unwrap(), 3);
+        assert!(parsed.next().is_none());
+    }
+}
@@ -0,0 +1,105 @@
+use futures::stream::Stream;
+use futures::{Async, Poll};
+
+use crate::{Chunks, Error};
+
+/// An item that carries its position in the source, such as the offset of a
+/// record in a log.
+pub trait HasOffset {
+    /// Returns the offset of the item.
+    fn offset(&self) -> u64;
+}
+
+/// An adaptor for `Chunks` that pairs every batch with the highest offset of
+/// its items.
+///
+/// Committing that offset after a batch was processed acknowledges the
+/// whole batch at once. This is created by `Chunks::with_offsets`.
+#[must_use = "streams do nothing unless polled"]
+pub struct OffsetChunks<S>
+where
+    S: Stream,
+{
+    chunks: Chunks<S>,
+}
+
+impl<S> OffsetChunks<S>
+where
+    S: Stream,
+    S::Item: HasOffset,
+{
+    pub(crate) fn new(chunks: Chunks<S>) -> OffsetChunks<S> {
+        OffsetChunks { chunks }
+    }
+
+    /// Acquires a reference to the underlying `Chunks`.
+    pub fn get_ref(&self) -> &Chunks<S> {
+        &self.chunks
+    }
+
+    /// Acquires a mutable reference to the underlying `Chunks`.
+    pub fn get_mut(&mut self) -> &mut Chunks<S> {
+        &mut self.chunks
+    }
+
+    /// Consumes this combinator, returning the underlying `Chunks`.
+    pub fn into_inner(self) -> Chunks<S> {
+        self.chunks
+    }
+}
+
+impl<S> Stream for OffsetChunks<S>
+where
+    S: Stream,
+    S::Item: HasOffset,
+{
+    type Item = (Vec<S::Item>, u64);
+    type Error = Error<S::Error>;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let batch = match self.chunks.poll()? {
+            Async::Ready(Some(batch)) => batch,
+            Async::Ready(None) => return Ok(Async::Ready(None)),
+            Async::NotReady => return Ok(Async::NotReady),
+        };
+        // Batches are never empty, and the offsets of a source are usually
+        // but not necessarily increasing.
+        let max_offset = batch.iter().map(HasOffset::offset).max().unwrap_or(0);
+        Ok(Async::Ready(Some((batch, max_offset))))
+    }
+}
+
+#[cfg(all(test, feature = "timer"))]
+mod tests {
+    use super::*;
+    use futures::stream;
+    use std::io;
+    use std::time::Duration;
+
+    #[derive(Debug, PartialEq)]
+    struct Record {
+        offset: u64,
+    }
+
+    impl HasOffset for Record {
+        fn offset(&self) -> u64 {
+            self.offset
+        }
+    }
+
+    #[test]
+    fn max_offset_per_batch() {
+        let records = vec![3, 4, 6, 7, 10]
+            .into_iter()
+            .map(|offset| Record { offset });
+        let stream = stream::iter_ok::<_, io::Error>(records);
+        let chunk_stream = Chunks::new(stream, 2, Duration::new(10, 0)).with_offsets();
+
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        let offsets: Vec<_> = v
+            .iter()
+            .map(|(batch, max_offset)| (batch.len(), *max_offset))
+            .collect();
+        assert_eq!(offsets, vec![(2, 4), (2, 7), (1, 10)]);
+    }
+}
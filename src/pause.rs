@@ -0,0 +1,58 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use futures::task::AtomicTask;
+
+/// A handle to pause and resume pulling items into a `Chunks`.
+///
+/// While paused, the inner stream is not polled, but the items that are
+/// already buffered are still flushed by the timer. The handle can be cloned
+/// and sent to other tasks.
+///
+/// This is created by `Chunks::pausable`.
+#[derive(Debug, Clone)]
+pub struct PauseHandle {
+    inner: Arc<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    paused: AtomicBool,
+    task: AtomicTask,
+}
+
+impl PauseHandle {
+    #[cfg(feature = "timer")]
+    pub(crate) fn new() -> PauseHandle {
+        PauseHandle {
+            inner: Arc::default(),
+        }
+    }
+
+    /// Stops pulling items from the inner stream.
+    pub fn pause(&self) {
+        self.inner.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Resumes pulling items from the inner stream, waking up the task that
+    /// drives the combinator.
+    pub fn resume(&self) {
+        self.inner.paused.store(false, Ordering::SeqCst);
+        self.inner.task.notify();
+    }
+
+    /// Returns whether pulling is currently paused.
+    pub fn is_paused(&self) -> bool {
+        self.inner.paused.load(Ordering::SeqCst)
+    }
+
+    /// Returns whether pulling is paused, making sure the current task is
+    /// woken up on `resume` if it is.
+    pub(crate) fn poll_paused(&self) -> bool {
+        if !self.is_paused() {
+            return false;
+        }
+        self.inner.task.register();
+        self.is_paused()
+    }
+}
@@ -0,0 +1,236 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+use futures::stream::Stream;
+use futures::{Async, Poll};
+
+use crate::{Chunks, Error, GrowthPolicy, MAX_PREALLOCATE};
+
+/// A pool of buffers that `PooledChunks` collects its batches into.
+///
+/// The pool can be cloned, and all clones share the same buffers. It starts
+/// out empty and fills up as the emitted batches are dropped.
+#[derive(Debug)]
+pub struct BufferPool<T> {
+    buffers: Arc<Mutex<Vec<Vec<T>>>>,
+}
+
+impl<T> BufferPool<T> {
+    /// Creates an empty pool.
+    pub fn new() -> BufferPool<T> {
+        BufferPool {
+            buffers: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Returns the number of buffers that are ready to be reused.
+    pub fn len(&self) -> usize {
+        self.buffers.lock().unwrap().len()
+    }
+
+    /// Returns whether no buffer is ready to be reused.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get(&self, capacity: usize) -> Vec<T> {
+        self.buffers
+            .lock()
+            .unwrap()
+            .pop()
+            .unwrap_or_else(|| Vec::with_capacity(capacity.min(MAX_PREALLOCATE)))
+    }
+
+    fn put(&self, mut buffer: Vec<T>) {
+        buffer.clear();
+        self.buffers.lock().unwrap().push(buffer);
+    }
+}
+
+impl<T> Clone for BufferPool<T> {
+    fn clone(&self) -> BufferPool<T> {
+        BufferPool {
+            buffers: self.buffers.clone(),
+        }
+    }
+}
+
+impl<T> Default for BufferPool<T> {
+    fn default() -> BufferPool<T> {
+        BufferPool::new()
+    }
+}
+
+/// A batch emitted by `PooledChunks`, which returns its buffer to the pool
+/// when it is dropped.
+#[derive(Debug)]
+pub struct PooledBatch<T> {
+    items: Vec<T>,
+    pool: BufferPool<T>,
+}
+
+impl<T> PooledBatch<T> {
+    /// Takes the items out of the batch, so its buffer is not returned to the
+    /// pool.
+    pub fn into_vec(mut self) -> Vec<T> {
+        std::mem::take(&mut self.items)
+    }
+}
+
+impl<T> Deref for PooledBatch<T> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Vec<T> {
+        &self.items
+    }
+}
+
+impl<T> DerefMut for PooledBatch<T> {
+    fn deref_mut(&mut self) -> &mut Vec<T> {
+        &mut self.items
+    }
+}
+
+impl<T> Drop for PooledBatch<T> {
+    fn drop(&mut self) {
+        // A buffer without an allocation is not worth keeping.
+        if self.items.capacity() > 0 {
+            self.pool.put(std::mem::take(&mut self.items));
+        }
+    }
+}
+
+/// An adaptor for `Chunks` that collects its batches into buffers from a
+/// `BufferPool`.
+///
+/// Every batch starts out in a buffer taken from the pool, and a new buffer
+/// is only allocated while the pool is empty. Once all batches in flight
+/// were dropped, the batcher runs without allocating. The growth policy of
+/// the `Chunks` is replaced, as the buffers come from the pool.
+///
+/// This is created by `Chunks::pooled`.
+#[must_use = "streams do nothing unless polled"]
+pub struct PooledChunks<S>
+where
+    S: Stream,
+{
+    chunks: Chunks<S>,
+    pool: BufferPool<S::Item>,
+}
+
+impl<S> PooledChunks<S>
+where
+    S: Stream,
+{
+    pub(crate) fn new(mut chunks: Chunks<S>, pool: BufferPool<S::Item>) -> PooledChunks<S> {
+        // Fresh buffers of the `Chunks` stay unallocated, so they can be
+        // replaced by one from the pool without a wasted allocation.
+        chunks.growth_policy = GrowthPolicy::Doubling;
+        let mut pooled = PooledChunks { chunks, pool };
+        pooled.refill();
+        pooled
+    }
+
+    /// Acquires a reference to the underlying `Chunks`.
+    pub fn get_ref(&self) -> &Chunks<S> {
+        &self.chunks
+    }
+
+    /// Acquires a mutable reference to the underlying `Chunks`.
+    pub fn get_mut(&mut self) -> &mut Chunks<S> {
+        &mut self.chunks
+    }
+
+    /// Consumes this combinator, returning the underlying `Chunks`.
+    pub fn into_inner(self) -> Chunks<S> {
+        self.chunks
+    }
+
+    /// Returns the pool the buffers are taken from.
+    pub fn pool(&self) -> &BufferPool<S::Item> {
+        &self.pool
+    }
+
+    fn refill(&mut self) {
+        let items = &mut self.chunks.items;
+        if items.is_empty() && items.capacity() == 0 {
+            *items = self.pool.get(self.chunks.capacity);
+        }
+    }
+}
+
+impl<S> Stream for PooledChunks<S>
+where
+    S: Stream,
+{
+    type Item = PooledBatch<S::Item>;
+    type Error = Error<S::Error>;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let items = match self.chunks.poll()? {
+            Async::Ready(Some(items)) => items,
+            Async::Ready(None) => return Ok(Async::Ready(None)),
+            Async::NotReady => return Ok(Async::NotReady),
+        };
+        self.refill();
+        Ok(Async::Ready(Some(PooledBatch {
+            items,
+            pool: self.pool.clone(),
+        })))
+    }
+}
+
+#[cfg(all(test, feature = "timer"))]
+mod tests {
+    use super::*;
+    use futures::stream;
+    use std::io;
+    use std::time::Duration;
+
+    #[test]
+    fn buffers_are_reused() {
+        let stream = stream::iter_ok::<_, io::Error>(0..8);
+        let pool = BufferPool::new();
+        let mut batches = Chunks::new(stream, 2, Duration::new(10, 0))
+            .pooled(pool.clone())
+            .wait()
+            .map(Result::unwrap);
+        let mut next = || batches.next();
+        let first = next().unwrap();
+        let ptr = first.as_ptr();
+        assert_eq!(*first, vec![0, 1]);
+        drop(first);
+        assert_eq!(pool.len(), 1);
+
+        // The buffer for the second batch was taken before the first one was
+        // returned, so the first buffer goes into the third batch.
+        let second = next().unwrap();
+        assert_ne!(second.as_ptr(), ptr);
+        assert!(pool.is_empty());
+        drop(second);
+
+        let third = next().unwrap();
+        assert_eq!(*third, vec![4, 5]);
+        assert_eq!(third.as_ptr(), ptr);
+        assert_eq!(third.capacity(), 2);
+
+        // A batch that is kept does not go back into the pool.
+        let fourth = next().unwrap().into_vec();
+        assert_eq!(fourth, vec![6, 7]);
+        assert!(next().is_none());
+        assert!(pool.is_empty());
+        drop(third);
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn weighted_buffers_are_bounded() {
+        let stream = stream::iter_ok::<_, io::Error>(vec![2, 2, 3]);
+        let batches = Chunks::weighted(stream, 4, Duration::new(10, 0), |&n| n)
+            .pooled(BufferPool::new())
+            .wait()
+            .map(|batch| batch.unwrap().into_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(batches, vec![vec![2, 2], vec![3]]);
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll};
+
+use crate::time::{clock, timer, Delay};
+#[cfg(feature = "timer")]
+use crate::MAX_PREALLOCATE;
+
+/// Token bucket limiting how many items are pulled from the inner stream.
+///
+/// Every pulled item spends a token which becomes available again `per`
+/// after it was spent, so no more than `max_items` are pulled within any
+/// window of length `per`.
+pub(crate) struct RateLimit {
+    max_items: usize,
+    per: Duration,
+    spent: VecDeque<Instant>,
+    refill: Option<Delay>,
+}
+
+impl RateLimit {
+    #[cfg(feature = "timer")]
+    pub(crate) fn new(max_items: usize, per: Duration) -> RateLimit {
+        RateLimit {
+            max_items,
+            per,
+            spent: VecDeque::with_capacity(max_items.min(MAX_PREALLOCATE)),
+            refill: None,
+        }
+    }
+
+    /// Checks whether a token is available, scheduling a wakeup for when the
+    /// next one is refilled otherwise.
+    pub(crate) fn poll_token(&mut self) -> Poll<(), timer::Error> {
+        let now = clock::now();
+        while let Some(&at) = self.spent.front() {
+            if at + self.per > now {
+                break;
+            }
+            self.spent.pop_front();
+        }
+
+        if self.spent.len() < self.max_items {
+            self.refill = None;
+            return Ok(Async::Ready(()));
+        }
+
+        let at = self.spent[0] + self.per;
+        match self.refill {
+            Some(ref mut refill) if refill.deadline() == at => {}
+            _ => self.refill = Some(Delay::new(at)),
+        }
+        if let Async::NotReady = self.refill.poll()? {
+            return Ok(Async::NotReady);
+        }
+
+        self.refill = None;
+        self.spent.pop_front();
+        Ok(Async::Ready(()))
+    }
+
+    /// Spends a token for an item that was just pulled.
+    pub(crate) fn spend(&mut self) {
+        self.spent.push_back(clock::now());
+    }
+}
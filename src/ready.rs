@@ -0,0 +1,92 @@
+use futures::stream::Stream;
+use futures::Async;
+
+use crate::{Batch, Chunks, Error};
+
+/// Iterator over the batches a `Chunks` can emit without waiting.
+///
+/// The iterator ends once the `Chunks` would have to wait for more items or
+/// for its timer, or once it completed. The batch that is being accumulated
+/// at that point stays buffered for later.
+///
+/// This is created by `Chunks::poll_ready_batches`.
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct ReadyBatches<'a, S, B = Vec<<S as Stream>::Item>>
+where
+    S: Stream,
+{
+    chunks: &'a mut Chunks<S, B>,
+    done: bool,
+}
+
+impl<'a, S, B> ReadyBatches<'a, S, B>
+where
+    S: Stream,
+    B: Batch<S::Item>,
+{
+    pub(crate) fn new(chunks: &'a mut Chunks<S, B>) -> ReadyBatches<'a, S, B> {
+        ReadyBatches {
+            chunks,
+            done: false,
+        }
+    }
+}
+
+impl<'a, S, B> Iterator for ReadyBatches<'a, S, B>
+where
+    S: Stream,
+    B: Batch<S::Item>,
+{
+    type Item = Result<B, Error<S::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.chunks.poll() {
+            Ok(Async::Ready(Some(batch))) => Some(Ok(batch)),
+            Ok(Async::Ready(None)) | Ok(Async::NotReady) => {
+                self.done = true;
+                None
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "timer"))]
+mod tests {
+    use super::*;
+    use futures::future;
+    use futures::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn drain_ready_batches() {
+        let (tx, rx) = mpsc::unbounded::<u32>();
+        for i in 0..7 {
+            tx.unbounded_send(i).unwrap();
+        }
+        let mut chunk_stream = Chunks::new(rx, 3, Duration::new(10, 0));
+
+        tokio::runtime::current_thread::block_on_all(future::lazy(move || {
+            let v: Vec<_> = chunk_stream
+                .poll_ready_batches()
+                .map(Result::unwrap)
+                .collect();
+            assert_eq!(v, vec![vec![0, 1, 2], vec![3, 4, 5]]);
+            assert_eq!(chunk_stream.snapshot(), vec![6]);
+
+            tx.unbounded_send(7).unwrap();
+            drop(tx);
+            let v: Vec<_> = chunk_stream
+                .poll_ready_batches()
+                .map(Result::unwrap)
+                .collect();
+            assert_eq!(v, vec![vec![6, 7]]);
+            assert_eq!(chunk_stream.poll_ready_batches().count(), 0);
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+    }
+}
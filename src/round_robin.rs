@@ -0,0 +1,78 @@
+use futures::stream::Stream;
+use futures::{Async, Poll};
+
+/// A stream polling several inner streams in turn.
+///
+/// Every call to `poll` resumes with the stream after the one that yielded
+/// the previous item, so a busy stream cannot starve the others. Inner
+/// streams are dropped once they complete, and the whole stream completes
+/// when all of them did.
+///
+/// This is created by `Chunks::from_streams`.
+#[must_use = "streams do nothing unless polled"]
+pub struct RoundRobin<S> {
+    streams: Vec<S>,
+    next: usize,
+}
+
+impl<S> RoundRobin<S>
+where
+    S: Stream,
+{
+    pub fn new(streams: Vec<S>) -> RoundRobin<S> {
+        RoundRobin { streams, next: 0 }
+    }
+
+    /// Acquires a reference to the underlying streams that are still running.
+    pub fn get_ref(&self) -> &[S] {
+        &self.streams
+    }
+
+    /// Consumes this combinator, returning the underlying streams that are
+    /// still running.
+    pub fn into_inner(self) -> Vec<S> {
+        self.streams
+    }
+}
+
+impl<S> Stream for RoundRobin<S>
+where
+    S: Stream,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let mut polled = 0;
+        while polled < self.streams.len() {
+            if self.next >= self.streams.len() {
+                self.next = 0;
+            }
+
+            match self.streams[self.next].poll() {
+                Ok(Async::Ready(Some(item))) => {
+                    self.next += 1;
+                    return Ok(Async::Ready(Some(item)));
+                }
+                // The next stream moves into this slot, so don't advance.
+                Ok(Async::Ready(None)) => {
+                    self.streams.remove(self.next);
+                }
+                Ok(Async::NotReady) => {
+                    self.next += 1;
+                    polled += 1;
+                }
+                Err(e) => {
+                    self.next += 1;
+                    return Err(e);
+                }
+            }
+        }
+
+        if self.streams.is_empty() {
+            Ok(Async::Ready(None))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use std::mem;
+
+use futures::{try_ready, Async, AsyncSink, Poll, Sink, StartSend};
+
+use crate::MAX_PREALLOCATE;
+
+/// A sink that collects items into batches before handing them to an inner
+/// sink of batches.
+///
+/// A batch is handed over once it holds `capacity` items. A partial batch is
+/// handed over by `poll_complete` and `close`, so closing the sink never drops
+/// the last items: `close` only completes once the partial batch was sent and
+/// the inner sink was closed as well.
+#[must_use = "sinks do nothing unless polled"]
+pub struct BatchSink<K, T> {
+    sink: K,
+    capacity: usize,
+    items: Vec<T>,
+}
+
+impl<K, T> BatchSink<K, T>
+where
+    K: Sink<SinkItem = Vec<T>>,
+{
+    /// Creates a new `BatchSink` forwarding batches of up to `capacity`
+    /// items into `sink`.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `capacity` is zero.
+    pub fn new(sink: K, capacity: usize) -> BatchSink<K, T> {
+        assert!(capacity > 0);
+
+        BatchSink {
+            sink,
+            capacity,
+            items: Vec::with_capacity(capacity.min(MAX_PREALLOCATE)),
+        }
+    }
+
+    /// Acquires a reference to the underlying sink.
+    pub fn get_ref(&self) -> &K {
+        &self.sink
+    }
+
+    /// Acquires a mutable reference to the underlying sink.
+    pub fn get_mut(&mut self) -> &mut K {
+        &mut self.sink
+    }
+
+    /// Consumes this combinator, returning the underlying sink.
+    ///
+    /// Note that this discards the items that were not handed over yet.
+    pub fn into_inner(self) -> K {
+        self.sink
+    }
+
+    /// Hands the buffered items over to the inner sink.
+    fn send_batch(&mut self) -> Poll<(), K::SinkError> {
+        if self.items.is_empty() {
+            return Ok(Async::Ready(()));
+        }
+        let batch = mem::replace(
+            &mut self.items,
+            Vec::with_capacity(self.capacity.min(MAX_PREALLOCATE)),
+        );
+        match self.sink.start_send(batch)? {
+            AsyncSink::Ready => Ok(Async::Ready(())),
+            AsyncSink::NotReady(batch) => {
+                self.items = batch;
+                Ok(Async::NotReady)
+            }
+        }
+    }
+}
+
+impl<K, T> Sink for BatchSink<K, T>
+where
+    K: Sink<SinkItem = Vec<T>>,
+{
+    type SinkItem = T;
+    type SinkError = K::SinkError;
+
+    fn start_send(&mut self, item: T) -> StartSend<T, K::SinkError> {
+        if self.items.len() >= self.capacity && self.send_batch()?.is_not_ready() {
+            return Ok(AsyncSink::NotReady(item));
+        }
+        self.items.push(item);
+        if self.items.len() >= self.capacity {
+            // A full batch that is not accepted yet is retried with the next
+            // item or when the sink is flushed.
+            self.send_batch()?;
+        }
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), K::SinkError> {
+        try_ready!(self.send_batch());
+        self.sink.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), K::SinkError> {
+        try_ready!(self.send_batch());
+        self.sink.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{stream, Future};
+
+    #[test]
+    fn close_flushes_partial_batch() {
+        let mut sink = BatchSink::new(Vec::new(), 5);
+        for i in 1..4 {
+            assert!(sink.start_send(i).unwrap().is_ready());
+        }
+        assert!(sink.get_ref().is_empty());
+
+        assert!(sink.close().unwrap().is_ready());
+        assert_eq!(sink.get_ref(), &vec![vec![1, 2, 3]]);
+
+        let (sink, _) = BatchSink::new(Vec::new(), 3)
+            .send_all(stream::iter_ok::<_, ()>(1..8))
+            .wait()
+            .unwrap();
+        assert_eq!(
+            sink.into_inner(),
+            vec![vec![1, 2, 3], vec![4, 5, 6], vec![7]]
+        );
+    }
+}
@@ -0,0 +1,309 @@
+use std::mem;
+use std::time::Duration;
+
+use futures::stream::{Fuse, Stream};
+use futures::{Async, Future, Poll};
+
+use crate::time::{clock, timer, Delay};
+use crate::{Error, Kind, MAX_PREALLOCATE};
+
+/// A batch emitted by `SplitChunks`, holding either successful items or
+/// errors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchOutput<T, E> {
+    /// A batch of the `Ok` items of the stream.
+    Ok(Vec<T>),
+
+    /// A batch of the `Err` items of the stream.
+    Errors(Vec<E>),
+}
+
+/// The buffer of one kind of items, with its own capacity and timer.
+struct Lane<T> {
+    items: Vec<T>,
+    capacity: usize,
+    clock: Option<Delay>,
+}
+
+impl<T> Lane<T> {
+    fn new(capacity: usize) -> Lane<T> {
+        Lane {
+            items: Vec::with_capacity(capacity.min(MAX_PREALLOCATE)),
+            capacity,
+            clock: None,
+        }
+    }
+
+    /// Buffers an item, returning the batch if it is full.
+    fn push(&mut self, item: T, duration: Duration) -> Option<Vec<T>> {
+        if self.items.is_empty() {
+            self.clock = Some(Delay::new(clock::now() + duration));
+        }
+        self.items.push(item);
+        if self.items.len() >= self.capacity {
+            self.take()
+        } else {
+            None
+        }
+    }
+
+    /// Returns whether the timer of a non-empty batch fired.
+    fn poll_clock(&mut self) -> Result<bool, timer::Error> {
+        match self.clock.poll()? {
+            Async::Ready(Some(())) => Ok(!self.items.is_empty()),
+            Async::Ready(None) | Async::NotReady => Ok(false),
+        }
+    }
+
+    fn take(&mut self) -> Option<Vec<T>> {
+        self.clock = None;
+        if self.items.is_empty() {
+            return None;
+        }
+        Some(mem::replace(
+            &mut self.items,
+            Vec::with_capacity(self.capacity.min(MAX_PREALLOCATE)),
+        ))
+    }
+}
+
+/// An adaptor for a stream of `Result`s that batches the successful items
+/// and the errors separately.
+///
+/// Both kinds of items are collected into batches of their own capacity,
+/// which are flushed when they are full or when their timeout fires, so the
+/// consumer can handle the good and the bad items in bulk. Errors of the stream
+/// itself are returned after all buffered items were emitted.
+#[must_use = "streams do nothing unless polled"]
+pub struct SplitChunks<S, T, E>
+where
+    S: Stream<Item = Result<T, E>>,
+{
+    duration: Duration,
+    oks: Lane<T>,
+    errors: Lane<E>,
+    err: Option<Error<S::Error>>,
+    draining: bool,
+    done: bool,
+    stream: Fuse<S>,
+}
+
+impl<S, T, E> SplitChunks<S, T, E>
+where
+    S: Stream<Item = Result<T, E>>,
+{
+    /// Creates a new `SplitChunks`, which batches up to `capacity` successful
+    /// items and up to `error_capacity` errors, and flushes a batch at the
+    /// latest `duration` after its first item.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `capacity` or `error_capacity` is zero.
+    pub fn new(
+        s: S,
+        capacity: usize,
+        error_capacity: usize,
+        duration: Duration,
+    ) -> SplitChunks<S, T, E> {
+        assert!(capacity > 0);
+        assert!(error_capacity > 0);
+
+        SplitChunks {
+            duration,
+            oks: Lane::new(capacity),
+            errors: Lane::new(error_capacity),
+            err: None,
+            draining: false,
+            done: false,
+            stream: s.fuse(),
+        }
+    }
+
+    /// Acquires a reference to the underlying stream that this combinator is
+    /// pulling from.
+    pub fn get_ref(&self) -> &S {
+        self.stream.get_ref()
+    }
+
+    /// Acquires a mutable reference to the underlying stream that this
+    /// combinator is pulling from.
+    ///
+    /// Note that care must be taken to avoid tampering with the state of the
+    /// stream which may otherwise confuse this combinator.
+    pub fn get_mut(&mut self) -> &mut S {
+        self.stream.get_mut()
+    }
+
+    /// Consumes this combinator, returning the underlying stream.
+    ///
+    /// Note that this may discard intermediate state of this combinator, so
+    /// care should be taken to avoid losing resources when this is called.
+    pub fn into_inner(self) -> S {
+        self.stream.into_inner()
+    }
+
+    /// Emits the buffered items, the successful ones first, and whatever
+    /// stopped the batching after them.
+    fn drain(&mut self, e: Option<Error<S::Error>>) {
+        self.err = e;
+        self.draining = true;
+    }
+}
+
+impl<S, T, E> Stream for SplitChunks<S, T, E>
+where
+    S: Stream<Item = Result<T, E>>,
+{
+    type Item = BatchOutput<T, E>;
+    type Error = Error<S::Error>;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.draining {
+            if let Some(batch) = self.oks.take() {
+                return Ok(Async::Ready(Some(BatchOutput::Ok(batch))));
+            }
+            if let Some(batch) = self.errors.take() {
+                return Ok(Async::Ready(Some(BatchOutput::Errors(batch))));
+            }
+            self.draining = false;
+            if let Some(e) = self.err.take() {
+                return Err(e);
+            }
+        }
+        if self.done {
+            return Ok(Async::Ready(None));
+        }
+
+        loop {
+            match self.stream.poll() {
+                Ok(Async::NotReady) => {}
+
+                Ok(Async::Ready(Some(Ok(item)))) => match self.oks.push(item, self.duration) {
+                    Some(batch) => return Ok(Async::Ready(Some(BatchOutput::Ok(batch)))),
+                    None => continue,
+                },
+
+                Ok(Async::Ready(Some(Err(item)))) => match self.errors.push(item, self.duration) {
+                    Some(batch) => return Ok(Async::Ready(Some(BatchOutput::Errors(batch)))),
+                    None => continue,
+                },
+
+                Ok(Async::Ready(None)) => {
+                    self.done = true;
+                    self.drain(None);
+                    return self.poll();
+                }
+
+                Err(e) => {
+                    self.drain(Some(Error::new(Kind::Inner(e))));
+                    return self.poll();
+                }
+            }
+
+            // Poll both timers, so the task is woken up by either of them. If
+            // one fails while the other fires, the batch of the other is
+            // emitted first and the error is kept for the next poll.
+            let batch = match (self.oks.poll_clock(), self.errors.poll_clock()) {
+                (Ok(true), errors) => {
+                    let batch = self.oks.take().map(BatchOutput::Ok);
+                    if let Err(e) = errors {
+                        self.drain(Some(Error::new(Kind::Timer(e))));
+                    }
+                    batch
+                }
+                (oks, Ok(true)) => {
+                    let batch = self.errors.take().map(BatchOutput::Errors);
+                    if let Err(e) = oks {
+                        self.drain(Some(Error::new(Kind::Timer(e))));
+                    }
+                    batch
+                }
+                (Err(e), _) | (_, Err(e)) => {
+                    self.drain(Some(Error::new(Kind::Timer(e))));
+                    return self.poll();
+                }
+                (Ok(false), Ok(false)) => None,
+            };
+            return Ok(match batch {
+                Some(batch) => Async::Ready(Some(batch)),
+                None => Async::NotReady,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{future, stream};
+    use std::io;
+    use std::time::Instant;
+
+    #[test]
+    fn separate_ok_and_error_batches() {
+        let items = vec![
+            Ok(1),
+            Err("a"),
+            Ok(2),
+            Ok(3),
+            Err("b"),
+            Err("c"),
+            Ok(4),
+            Ok(5),
+            Err("d"),
+        ];
+        let stream = stream::iter_ok::<_, io::Error>(items);
+        let split = SplitChunks::new(stream, 2, 3, Duration::new(10, 0));
+
+        let v = tokio::runtime::current_thread::block_on_all(split.collect()).unwrap();
+        assert_eq!(
+            v,
+            vec![
+                BatchOutput::Ok(vec![1, 2]),
+                BatchOutput::Errors(vec!["a", "b", "c"]),
+                BatchOutput::Ok(vec![3, 4]),
+                BatchOutput::Ok(vec![5]),
+                BatchOutput::Errors(vec!["d"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn timer_error_after_the_other_lane_fires() {
+        use tokio_executor::park::ParkThread;
+        use tokio_timer::Timer;
+
+        let stream = stream::poll_fn(|| -> Poll<Option<Result<u32, &str>>, io::Error> {
+            Ok(Async::NotReady)
+        });
+        let mut split = SplitChunks::new(stream, 2, 2, Duration::new(10, 0));
+        let mut started = false;
+
+        tokio::runtime::current_thread::block_on_all(future::poll_fn(move || {
+            if !started {
+                started = true;
+                assert_eq!(split.oks.push(1, Duration::new(0, 0)), None);
+            }
+            // Wait until the oks lane is due, then shut down the timer of
+            // the errors lane.
+            futures::try_ready!(split.oks.clock.poll().map_err(drop));
+            let handle = Timer::new(ParkThread::new()).handle();
+            split.errors.items.push("a");
+            split.errors.clock = Some(handle.delay(Instant::now()));
+
+            assert_eq!(
+                split.poll().unwrap(),
+                Async::Ready(Some(BatchOutput::Ok(vec![1])))
+            );
+            // The error is reported only once.
+            split.errors.clock = Some(Delay::new(Instant::now() + Duration::new(10, 0)));
+            assert_eq!(
+                split.poll().unwrap(),
+                Async::Ready(Some(BatchOutput::Errors(vec!["a"])))
+            );
+            assert!(split.poll().unwrap_err().is_timer());
+            Ok::<_, ()>(Async::Ready(()))
+        }))
+        .unwrap();
+    }
+}
@@ -0,0 +1,21 @@
+/// The state a `Chunks` is in between two polls.
+///
+/// This is derived from the fields of the combinator rather than stored, so
+/// it cannot go out of sync with them. `poll` dispatches on it before it
+/// pulls any items.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum State {
+    /// Nothing is buffered and the inner stream may yield more items.
+    Idle,
+
+    /// A batch is being accumulated.
+    Accumulating,
+
+    /// An error was deferred until the batch before it was emitted, and is
+    /// returned by the next poll.
+    PendingError,
+
+    /// The inner stream ended or the stream was shut down. Items that the
+    /// validation buffered again are still emitted as the final batch.
+    Done,
+}
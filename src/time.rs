@@ -0,0 +1,74 @@
+//! The timer used by the combinators.
+//!
+//! With the `timer` feature, which is enabled by default, this is the timer
+//! of tokio. Without it, `Delay` never fires, so only batching by capacity is
+//! available, e.g. with `Chunks::with_capacity`.
+
+#[cfg(feature = "timer")]
+pub(crate) use tokio::clock;
+#[cfg(feature = "timer")]
+pub(crate) use tokio::timer::{self, Delay};
+
+#[cfg(not(feature = "timer"))]
+pub(crate) use self::disabled::{clock, timer, Delay};
+
+#[cfg(not(feature = "timer"))]
+mod disabled {
+    use std::time::Instant;
+
+    use futures::{Async, Future, Poll};
+
+    pub(crate) mod clock {
+        use std::time::Instant;
+
+        pub(crate) fn now() -> Instant {
+            Instant::now()
+        }
+    }
+
+    pub(crate) mod timer {
+        use std::error;
+        use std::fmt;
+
+        /// Error of the timer, which cannot occur without the `timer` feature.
+        #[derive(Debug)]
+        pub struct Error(());
+
+        impl fmt::Display for Error {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("timer is disabled")
+            }
+        }
+
+        impl error::Error for Error {}
+    }
+
+    /// A timer that never fires.
+    #[derive(Debug)]
+    pub(crate) struct Delay {
+        deadline: Instant,
+    }
+
+    impl Delay {
+        pub(crate) fn new(deadline: Instant) -> Delay {
+            Delay { deadline }
+        }
+
+        pub(crate) fn deadline(&self) -> Instant {
+            self.deadline
+        }
+
+        pub(crate) fn reset(&mut self, deadline: Instant) {
+            self.deadline = deadline;
+        }
+    }
+
+    impl Future for Delay {
+        type Item = ();
+        type Error = timer::Error;
+
+        fn poll(&mut self) -> Poll<(), timer::Error> {
+            Ok(Async::NotReady)
+        }
+    }
+}
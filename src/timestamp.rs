@@ -0,0 +1,129 @@
+use std::time::{Duration, Instant};
+
+use futures::stream::Stream;
+use futures::{Async, Poll};
+
+use crate::time::clock;
+use crate::{Chunks, Error, FlushReason};
+
+/// A batch together with the times it was collected in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Batched<T> {
+    /// The items of the batch.
+    pub items: Vec<T>,
+
+    /// When the first item of the batch was pulled from the inner stream.
+    pub first_item_at: Instant,
+
+    /// When the batch was flushed.
+    pub emitted_at: Instant,
+}
+
+/// A batch together with why it was flushed and how long it was collected.
+///
+/// This is returned by `Chunks::poll_batch_info`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchInfo<T> {
+    /// The items of the batch.
+    pub items: Vec<T>,
+
+    /// The trigger that flushed the batch.
+    pub reason: FlushReason,
+
+    /// The time from the arrival of the first item until the flush.
+    pub age: Duration,
+}
+
+/// An adaptor for `Chunks` that tags every batch with the time its first
+/// item arrived and the time it was flushed.
+///
+/// The difference of the two is the latency that batching added to the
+/// first item. This is created by `Chunks::timestamped`. Should the times of
+/// a batch not be known, both are the instant it was polled.
+#[must_use = "streams do nothing unless polled"]
+pub struct Timestamped<S>
+where
+    S: Stream,
+{
+    chunks: Chunks<S>,
+}
+
+impl<S> Timestamped<S>
+where
+    S: Stream,
+{
+    pub(crate) fn new(chunks: Chunks<S>) -> Timestamped<S> {
+        Timestamped { chunks }
+    }
+
+    /// Acquires a reference to the underlying `Chunks`.
+    pub fn get_ref(&self) -> &Chunks<S> {
+        &self.chunks
+    }
+
+    /// Acquires a mutable reference to the underlying `Chunks`.
+    pub fn get_mut(&mut self) -> &mut Chunks<S> {
+        &mut self.chunks
+    }
+
+    /// Consumes this combinator, returning the underlying `Chunks`.
+    pub fn into_inner(self) -> Chunks<S> {
+        self.chunks
+    }
+}
+
+impl<S> Stream for Timestamped<S>
+where
+    S: Stream,
+{
+    type Item = Batched<S::Item>;
+    type Error = Error<S::Error>;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let items = match self.chunks.poll()? {
+            Async::Ready(Some(items)) => items,
+            Async::Ready(None) => return Ok(Async::Ready(None)),
+            Async::NotReady => return Ok(Async::NotReady),
+        };
+        let (first_item_at, emitted_at) = self.chunks.flush_times().unwrap_or_else(|| {
+            let now = clock::now();
+            (now, now)
+        });
+        Ok(Async::Ready(Some(Batched {
+            items,
+            first_item_at,
+            emitted_at,
+        })))
+    }
+}
+
+#[cfg(all(test, feature = "timer"))]
+mod tests {
+    use super::*;
+    use futures::{stream, Future};
+    use std::io;
+    use std::time::Duration;
+    use tokio::timer::Delay;
+
+    #[test]
+    fn timestamps() {
+        let late = Delay::new(Instant::now() + Duration::from_millis(50))
+            .then(|_| Ok::<_, io::Error>(stream::iter_ok(vec![4, 5, 6])))
+            .flatten_stream();
+        let stream = stream::iter_ok::<_, io::Error>(vec![1, 2, 3]).chain(late);
+        let chunk_stream = Chunks::new(stream, 2, Duration::from_millis(20)).timestamped();
+
+        let v = tokio::runtime::current_thread::block_on_all(chunk_stream.collect()).unwrap();
+        let items: Vec<_> = v.iter().map(|batch| batch.items.clone()).collect();
+        assert_eq!(items, vec![vec![1, 2], vec![3], vec![4, 5], vec![6]]);
+
+        for batch in &v {
+            assert!(batch.emitted_at >= batch.first_item_at);
+        }
+        for pair in v.windows(2) {
+            assert!(pair[1].first_item_at >= pair[0].emitted_at);
+        }
+        // The lonely item waited for the timer.
+        assert!(v[1].emitted_at - v[1].first_item_at >= Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,89 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use futures::task::AtomicTask;
+
+/// A condition that flushes a batch once it holds.
+///
+/// After every item the enabled triggers are checked in the order of
+/// `Trigger::ORDER`, and the first one that holds names the reason of the
+/// flush. The timer is the one trigger that fires on its own, independent of
+/// the items.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Trigger {
+    /// The batch reached the hard cap, which overrides all other triggers.
+    HardCap,
+
+    /// The batch reached its capacity.
+    Count,
+
+    /// The weights of the items reached `max_weight`.
+    Weight,
+
+    /// The buffered items took up `max_memory`.
+    Memory,
+
+    /// The item matched the boundary of `flush_on`.
+    Boundary,
+
+    /// A flush was requested through a `FlushHandle`.
+    Signal,
+}
+
+impl Trigger {
+    pub(crate) const ORDER: [Trigger; 6] = [
+        Trigger::HardCap,
+        Trigger::Count,
+        Trigger::Weight,
+        Trigger::Memory,
+        Trigger::Boundary,
+        Trigger::Signal,
+    ];
+}
+
+/// A handle to flush the batch of a `Chunks` from the outside.
+///
+/// Requesting a flush while nothing is buffered has no effect. The handle can
+/// be cloned and sent to other tasks.
+///
+/// This is created by `Chunks::flush_handle`.
+#[derive(Debug, Clone, Default)]
+pub struct FlushHandle {
+    inner: Arc<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    requested: AtomicBool,
+    task: AtomicTask,
+}
+
+impl FlushHandle {
+    pub(crate) fn new() -> FlushHandle {
+        FlushHandle::default()
+    }
+
+    /// Flushes the items buffered so far, waking up the task that drives the
+    /// combinator.
+    pub fn flush(&self) {
+        self.inner.requested.store(true, Ordering::SeqCst);
+        self.inner.task.notify();
+    }
+
+    /// Returns whether a flush was requested and not served yet.
+    pub(crate) fn is_requested(&self) -> bool {
+        self.inner.requested.load(Ordering::SeqCst)
+    }
+
+    /// Returns whether a flush was requested, making sure the current task is
+    /// woken up on the next request if it was not.
+    pub(crate) fn poll_requested(&self) -> bool {
+        self.inner.task.register();
+        self.is_requested()
+    }
+
+    /// Marks a pending request as served.
+    pub(crate) fn clear(&self) {
+        self.inner.requested.store(false, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use futures::stream::{Fuse, Stream};
+use futures::{Async, Future, Poll};
+
+use crate::time::{clock, Delay};
+use crate::{Error, Kind, MAX_PREALLOCATE};
+
+/// An adaptor that emits sliding windows over the elements of a stream.
+///
+/// This adaptor keeps the last `window` items of the stream and passes on a
+/// copy of them every time `step` new items have arrived. If new items have
+/// arrived but the next window is not complete when the timeout triggers, the
+/// current window is emitted early.
+#[must_use = "streams do nothing unless polled"]
+pub struct WindowChunks<S>
+where
+    S: Stream,
+{
+    clock: Option<Delay>,
+    duration: Duration,
+    window: usize,
+    step: usize,
+    pending: usize,
+    items: VecDeque<S::Item>,
+    err: Option<Error<S::Error>>,
+    stream: Fuse<S>,
+}
+
+impl<S> WindowChunks<S>
+where
+    S: Stream,
+    S::Item: Clone,
+{
+    pub fn new(s: S, window: usize, step: usize, duration: Duration) -> WindowChunks<S> {
+        assert!(window > 0);
+        assert!(step > 0);
+
+        WindowChunks {
+            clock: None,
+            duration,
+            window,
+            step,
+            pending: 0,
+            items: VecDeque::with_capacity(window.min(MAX_PREALLOCATE)),
+            err: None,
+            stream: s.fuse(),
+        }
+    }
+
+    /// Acquires a reference to the underlying stream that this combinator is
+    /// pulling from.
+    pub fn get_ref(&self) -> &S {
+        self.stream.get_ref()
+    }
+
+    /// Acquires a mutable reference to the underlying stream that this
+    /// combinator is pulling from.
+    ///
+    /// Note that care must be taken to avoid tampering with the state of the
+    /// stream which may otherwise confuse this combinator.
+    pub fn get_mut(&mut self) -> &mut S {
+        self.stream.get_mut()
+    }
+
+    /// Consumes this combinator, returning the underlying stream.
+    ///
+    /// Note that this may discard intermediate state of this combinator, so
+    /// care should be taken to avoid losing resources when this is called.
+    pub fn into_inner(self) -> S {
+        self.stream.into_inner()
+    }
+
+    fn flush(&mut self) -> Poll<Option<Vec<S::Item>>, Error<S::Error>> {
+        self.clock = None;
+        self.pending = 0;
+        Ok(Some(self.items.iter().cloned().collect()).into())
+    }
+}
+
+impl<S> Stream for WindowChunks<S>
+where
+    S: Stream,
+    S::Item: Clone,
+{
+    type Item = Vec<<S as Stream>::Item>;
+    type Error = Error<S::Error>;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if let Some(e) = self.err.take() {
+            return Err(e);
+        }
+
+        loop {
+            match self.stream.poll() {
+                Ok(Async::NotReady) => {}
+
+                // Slide the window by one item and emit it once enough new
+                // items have arrived since the last emission.
+                Ok(Async::Ready(Some(item))) => {
+                    if self.pending == 0 {
+                        self.clock = Some(Delay::new(clock::now() + self.duration));
+                    }
+                    if self.items.len() == self.window {
+                        self.items.pop_front();
+                    }
+                    self.items.push_back(item);
+                    self.pending += 1;
+                    if self.items.len() == self.window && self.pending >= self.step {
+                        return self.flush();
+                    } else {
+                        continue;
+                    }
+                }
+
+                // Emit the last window if it contains items that were not
+                // part of an emitted window yet.
+                Ok(Async::Ready(None)) => {
+                    return if self.pending > 0 {
+                        self.flush()
+                    } else {
+                        Ok(Async::Ready(None))
+                    };
+                }
+
+                Err(e) => {
+                    if self.pending == 0 {
+                        return Err(Error::new(Kind::Inner(e)));
+                    } else {
+                        self.err = Some(Error::new(Kind::Inner(e)));
+                        return self.flush();
+                    }
+                }
+            }
+
+            match self.clock.poll() {
+                Ok(Async::Ready(Some(()))) => {
+                    return self.flush();
+                }
+                Ok(Async::Ready(None)) | Ok(Async::NotReady) => {}
+                Err(e) => {
+                    if self.pending == 0 {
+                        return Err(Error::new(Kind::Timer(e)));
+                    } else {
+                        self.err = Some(Error::new(Kind::Timer(e)));
+                        return self.flush();
+                    }
+                }
+            }
+
+            return Ok(Async::NotReady);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "timer"))]
+mod tests {
+    use super::*;
+    use futures::stream;
+    use std::io;
+
+    #[test]
+    fn sliding_windows() {
+        let iter = vec![1, 2, 3, 4].into_iter();
+        let stream = stream::iter_ok::<_, io::Error>(iter);
+
+        let window_stream = WindowChunks::new(stream, 3, 1, Duration::new(10, 0));
+
+        let v = window_stream.collect();
+        tokio::run(v.then(|res| {
+            match res {
+                Err(e) => panic!("{:?}", e),
+                Ok(v) => assert_eq!(vec![vec![1, 2, 3], vec![2, 3, 4]], v),
+            };
+            Ok(())
+        }));
+    }
+
+    #[test]
+    fn window_step() {
+        let iter = vec![1, 2, 3, 4, 5, 6, 7].into_iter();
+        let stream = stream::iter_ok::<_, io::Error>(iter);
+
+        let window_stream = WindowChunks::new(stream, 4, 2, Duration::new(10, 0));
+
+        let v = window_stream.collect();
+        tokio::run(v.then(|res| {
+            match res {
+                Err(e) => panic!("{:?}", e),
+                Ok(v) => assert_eq!(
+                    vec![vec![1, 2, 3, 4], vec![3, 4, 5, 6], vec![4, 5, 6, 7]],
+                    v
+                ),
+            };
+            Ok(())
+        }));
+    }
+
+    #[test]
+    fn unbounded_window() {
+        let stream = stream::iter_ok::<_, io::Error>(vec![1, 2]);
+
+        let window_stream = WindowChunks::new(stream, usize::MAX, 1, Duration::new(10, 0));
+
+        let v = tokio::runtime::current_thread::block_on_all(window_stream.collect()).unwrap();
+        assert_eq!(v, vec![vec![1, 2]]);
+    }
+}
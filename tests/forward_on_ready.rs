@@ -0,0 +1,111 @@
+//! Tests for flushing batches into a sink whenever it is ready.
+#![cfg(feature = "timer")]
+
+use std::cell::RefCell;
+use std::io;
+use std::mem;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::sync::mpsc;
+use futures::{future, Async, AsyncSink, Future, Poll, Sink, StartSend};
+use tokio::runtime::current_thread;
+use tokio_batch::{Chunks, FlushReason};
+
+/// The state of a `ToggleSink`, shared with the test.
+#[derive(Default)]
+struct Shared {
+    ready: bool,
+    in_flight: Vec<Vec<u32>>,
+    sent: Vec<Vec<u32>>,
+}
+
+/// A sink that only accepts and completes batches while it is ready.
+struct ToggleSink(Rc<RefCell<Shared>>);
+
+impl Sink for ToggleSink {
+    type SinkItem = Vec<u32>;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, batch: Vec<u32>) -> StartSend<Vec<u32>, io::Error> {
+        let mut shared = self.0.borrow_mut();
+        if !shared.ready {
+            return Ok(AsyncSink::NotReady(batch));
+        }
+        shared.in_flight.push(batch);
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        let mut shared = self.0.borrow_mut();
+        if !shared.ready {
+            return Ok(Async::NotReady);
+        }
+        let mut in_flight = mem::take(&mut shared.in_flight);
+        shared.sent.append(&mut in_flight);
+        Ok(Async::Ready(()))
+    }
+
+    fn close(&mut self) -> Poll<(), io::Error> {
+        self.poll_complete()
+    }
+}
+
+#[test]
+fn flushes_on_readiness_transitions() {
+    let (tx, rx) = mpsc::unbounded::<u32>();
+    let shared = Rc::new(RefCell::new(Shared {
+        ready: true,
+        ..Shared::default()
+    }));
+    let mut forward =
+        Chunks::new(rx, 4, Duration::new(10, 0)).forward_on_ready(ToggleSink(shared.clone()));
+
+    let set_ready = |ready: bool| shared.borrow_mut().ready = ready;
+    let sent = || shared.borrow().sent.clone();
+
+    current_thread::block_on_all(future::lazy(|| {
+        // A ready sink gets every item as soon as the source is idle.
+        tx.unbounded_send(1).unwrap();
+        assert!(forward.poll().unwrap().is_not_ready());
+        assert_eq!(sent(), vec![vec![1]]);
+
+        // A busy sink lets the items pile up until it is ready again.
+        set_ready(false);
+        tx.unbounded_send(2).unwrap();
+        tx.unbounded_send(3).unwrap();
+        assert!(forward.poll().unwrap().is_not_ready());
+        assert_eq!(sent(), vec![vec![1]]);
+
+        set_ready(true);
+        assert!(forward.poll().unwrap().is_not_ready());
+        assert_eq!(sent(), vec![vec![1], vec![2, 3]]);
+
+        // A full batch is held back while the sink is busy, and the items
+        // behind it are flushed once it accepted the batch.
+        set_ready(false);
+        for i in 4..10 {
+            tx.unbounded_send(i).unwrap();
+        }
+        assert!(forward.poll().unwrap().is_not_ready());
+        assert_eq!(sent(), vec![vec![1], vec![2, 3]]);
+
+        set_ready(true);
+        assert!(forward.poll().unwrap().is_not_ready());
+        assert_eq!(
+            sent(),
+            vec![vec![1], vec![2, 3], vec![4, 5, 6, 7], vec![8, 9]]
+        );
+
+        drop(tx);
+        match forward.poll().unwrap() {
+            Async::Ready((chunks, _)) => {
+                assert_eq!(chunks.batches_emitted(), 4);
+                assert_eq!(chunks.last_flush_reason(), Some(FlushReason::Signal));
+            }
+            Async::NotReady => panic!("forwarding should complete"),
+        }
+        Ok::<_, ()>(())
+    }))
+    .unwrap();
+}
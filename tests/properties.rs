@@ -0,0 +1,234 @@
+//! Randomized tests for the invariants of `Chunks`.
+//!
+//! Every case feeds a random sequence of items into a `Chunks` with a random
+//! capacity, interleaved with random jumps of a mock clock, and checks the
+//! emitted batches. The cases are generated from fixed seeds, so a failure is
+//! reproducible from the seed in the panic message.
+//!
+//! The number of cases per test can be raised to fuzz for longer, e.g.
+//!
+//! ```text
+//! PROPERTY_CASES=100000 cargo test --release --test properties
+//! ```
+#![cfg(feature = "timer")]
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::executor::{self, Notify, NotifyHandle, Spawn};
+use futures::{Async, Poll, Stream};
+use tokio_batch::Chunks;
+use tokio_executor::park::ParkThread;
+use tokio_timer::clock::{self, Clock, Now};
+use tokio_timer::timer::{self, Timer};
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A linear congruential generator, good enough to pick test cases.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: u64) -> u64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        (self.0 >> 33) % n
+    }
+}
+
+/// A clock that only moves when it is told to.
+#[derive(Clone)]
+struct MockNow(Arc<Mutex<Instant>>);
+
+impl Now for MockNow {
+    fn now(&self) -> Instant {
+        *self.0.lock().unwrap()
+    }
+}
+
+struct NoopNotify;
+
+impl Notify for NoopNotify {
+    fn notify(&self, _: usize) {}
+}
+
+/// Returns the number of cases to run per test.
+fn cases() -> u64 {
+    std::env::var("PROPERTY_CASES")
+        .ok()
+        .and_then(|cases| cases.parse().ok())
+        .unwrap_or(500)
+}
+
+/// A scripted response of the source.
+enum Response {
+    Item(u32),
+    Error(u32),
+    NotReady,
+}
+
+/// A source whose responses are handed in by the test. It is pending while
+/// it has no responses and ends once it is closed.
+#[derive(Default)]
+struct Source {
+    responses: VecDeque<Response>,
+    closed: bool,
+}
+
+struct SourceStream(Rc<RefCell<Source>>);
+
+impl Stream for SourceStream {
+    type Item = u32;
+    type Error = u32;
+
+    fn poll(&mut self) -> Poll<Option<u32>, u32> {
+        let mut source = self.0.borrow_mut();
+        match source.responses.pop_front() {
+            Some(Response::Item(item)) => Ok(Async::Ready(Some(item))),
+            Some(Response::Error(id)) => Err(id),
+            Some(Response::NotReady) => Ok(Async::NotReady),
+            None if source.closed => Ok(Async::Ready(None)),
+            None => Ok(Async::NotReady),
+        }
+    }
+}
+
+/// What the `Chunks` emitted, in order.
+#[derive(Debug, PartialEq)]
+enum Event {
+    Batch(Vec<u32>),
+    Error(u32),
+}
+
+/// Polls until the stream is pending, returning whether it ended.
+fn drain(chunk_stream: &mut Spawn<Chunks<SourceStream>>, events: &mut Vec<Event>) -> bool {
+    let notify = NotifyHandle::from(Arc::new(NoopNotify));
+    loop {
+        match chunk_stream.poll_stream_notify(&notify, 0) {
+            Ok(Async::Ready(Some(batch))) => events.push(Event::Batch(batch)),
+            Ok(Async::Ready(None)) => return true,
+            Ok(Async::NotReady) => return false,
+            Err(e) => events.push(Event::Error(e.into_inner().expect("not an inner error"))),
+        }
+    }
+}
+
+/// Runs one random case and checks the invariants of the emitted batches,
+/// returning the number of batches flushed by the timer.
+///
+/// With `faults`, the source also fails and stalls at random, and the errors
+/// have to come out in the same order relative to the items.
+fn check(seed: u64, faults: bool) -> u64 {
+    let mut rng = Rng(seed);
+    let capacity = 1 + rng.below(8) as usize;
+
+    let now = MockNow(Arc::new(Mutex::new(Instant::now())));
+    let clock = Clock::new_with_now(now.clone());
+    let mut timer = Timer::new_with_now(ParkThread::new(), clock.clone());
+    let handle = timer.handle();
+    let mut enter = tokio_executor::enter().unwrap();
+
+    let source = Rc::new(RefCell::new(Source::default()));
+    let chunk_stream = Chunks::new(SourceStream(source.clone()), capacity, TIMEOUT);
+    let mut chunk_stream = executor::spawn(chunk_stream);
+
+    // Items as `Ok` and errors as `Err`, in the order they were scripted.
+    let mut input = Vec::new();
+    let mut events = Vec::new();
+    let mut next = 0;
+    let mut timeouts = 0;
+
+    clock::with_default(&clock, &mut enter, |enter| {
+        timer::with_default(&handle, enter, |_| {
+            for _ in 0..1 + rng.below(40) {
+                match rng.below(4) {
+                    // Jump by no time at all, by less than the timeout or by
+                    // more than the timeout.
+                    0 => {
+                        let jump = TIMEOUT * rng.below(3) as u32 * 2 / 3;
+                        // Responses held back by a stall are pulled after the
+                        // timer flushed, into a fresh batch.
+                        let drained = source.borrow().responses.is_empty();
+                        *now.0.lock().unwrap() += jump;
+                        timer.turn(Some(Duration::from_millis(0))).unwrap();
+                        drain(&mut chunk_stream, &mut events);
+                        if jump > TIMEOUT && drained {
+                            assert!(
+                                chunk_stream.get_ref().snapshot().is_empty(),
+                                "seed {}: items outlived the timeout",
+                                seed
+                            );
+                        }
+                    }
+                    _ => {
+                        for _ in 0..rng.below(6) {
+                            let response = match rng.below(if faults { 8 } else { 1 }) {
+                                6 => Response::Error(next),
+                                7 => Response::NotReady,
+                                _ => Response::Item(next),
+                            };
+                            match response {
+                                Response::Item(item) => input.push(Ok(item)),
+                                Response::Error(id) => input.push(Err(id)),
+                                Response::NotReady => {}
+                            }
+                            source.borrow_mut().responses.push_back(response);
+                            next += 1;
+                        }
+                        drain(&mut chunk_stream, &mut events);
+                    }
+                }
+            }
+
+            // Every scripted stall ends a drain early.
+            source.borrow_mut().closed = true;
+            let stalls = source.borrow().responses.len();
+            assert!(
+                (0..=stalls).any(|_| drain(&mut chunk_stream, &mut events)),
+                "seed {}: stream did not end",
+                seed
+            );
+            timeouts = chunk_stream.get_ref().counters().timeouts;
+        })
+    });
+
+    let mut output = Vec::new();
+    for event in events {
+        match event {
+            Event::Batch(batch) => {
+                assert!(!batch.is_empty(), "seed {}: empty batch", seed);
+                assert!(
+                    batch.len() <= capacity,
+                    "seed {}: batch of {} exceeds capacity {}",
+                    seed,
+                    batch.len(),
+                    capacity
+                );
+                output.extend(batch.into_iter().map(Ok));
+            }
+            Event::Error(id) => output.push(Err(id)),
+        }
+    }
+    assert_eq!(
+        output, input,
+        "seed {}: items or errors were lost or reordered",
+        seed
+    );
+    timeouts
+}
+
+#[test]
+fn batching_invariants() {
+    let timeouts: u64 = (0..cases()).map(|seed| check(seed, false)).sum();
+    assert!(timeouts > 0, "the mock clock never fired");
+}
+
+#[test]
+fn error_interleaving() {
+    let timeouts: u64 = (0..cases()).map(|seed| check(seed, true)).sum();
+    assert!(timeouts > 0, "the mock clock never fired");
+}
@@ -0,0 +1,56 @@
+//! Tests for running a `Chunks` on its own task.
+#![cfg(feature = "timer")]
+
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{future, stream, Future, Stream};
+use tokio::runtime::Runtime;
+use tokio_batch::Chunks;
+
+#[test]
+fn spawn_receives_all_batches() {
+    let mut rt = Runtime::new().unwrap();
+
+    let received = rt
+        .block_on(future::lazy(|| {
+            let stream = stream::iter_ok::<_, io::Error>(0..10);
+            let rx = Chunks::new(stream, 3, Duration::new(10, 0)).spawn();
+            rx.collect()
+        }))
+        .unwrap();
+    let batches: Vec<Vec<u32>> = received.into_iter().map(Result::unwrap).collect();
+    assert_eq!(
+        batches,
+        vec![vec![0, 1, 2], vec![3, 4, 5], vec![6, 7, 8], vec![9]]
+    );
+
+    rt.shutdown_on_idle().wait().unwrap();
+}
+
+#[test]
+fn spawn_stops_when_receiver_is_dropped() {
+    let mut rt = Runtime::new().unwrap();
+
+    let pulled = Arc::new(AtomicUsize::new(0));
+    let counter = pulled.clone();
+    let stream = stream::repeat::<_, io::Error>(1).inspect(move |_| {
+        counter.fetch_add(1, Ordering::SeqCst);
+    });
+
+    let first = rt
+        .block_on(future::lazy(|| {
+            let rx = Chunks::new(stream, 2, Duration::new(10, 0)).spawn();
+            rx.into_future().map_err(|_| ())
+        }))
+        .unwrap()
+        .0;
+    assert_eq!(first.unwrap().unwrap(), vec![1, 1]);
+
+    // The runtime only becomes idle once the task noticed that the receiver
+    // is gone, as the source never ends.
+    rt.shutdown_on_idle().wait().unwrap();
+    assert!(pulled.load(Ordering::SeqCst) < 100);
+}